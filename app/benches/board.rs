@@ -0,0 +1,56 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_codenames::{
+    game::{Game, GameConfig},
+    words::{WordList, WordLists},
+};
+
+/// A config for a `size` by `size` board with roughly the default card ratios.
+fn square_config(size: usize) -> GameConfig {
+    let card_count = size * size;
+    let starting_team_cards = card_count * 9 / 25;
+    let other_team_cards = starting_team_cards - 1;
+    let assassins = 1;
+    GameConfig {
+        rows: size,
+        cols: size,
+        starting_team_cards,
+        other_team_cards,
+        assassins,
+        bystanders: card_count - starting_team_cards - other_team_cards - assassins,
+        ..GameConfig::default()
+    }
+}
+
+fn generated_words(name: &str, count: usize) -> WordList {
+    WordList::new(name, (0..count).map(|i| format!("WORD{}", i)).collect())
+}
+
+fn create_board(c: &mut Criterion) {
+    let default_list = WordLists::builtin().default_list().clone();
+    let large_list = generated_words("large", 10_000);
+
+    let mut group = c.benchmark_group("create_board");
+    for size in [5, 7, 10] {
+        let config = square_config(size);
+        // Fail here rather than in the middle of a benchmark if the limits change
+        if let Err(e) = config.validate() {
+            panic!("The {}x{} benchmark config is invalid: {}", size, size, e);
+        }
+        let exact_list = generated_words("exact", config.card_count());
+        if default_list.words.len() >= config.card_count() {
+            group.bench_with_input(BenchmarkId::new("default_list", size), &config, |b, config| {
+                b.iter(|| Game::new(black_box(config), &default_list, 0).unwrap())
+            });
+        }
+        group.bench_with_input(BenchmarkId::new("large_list", size), &config, |b, config| {
+            b.iter(|| Game::new(black_box(config), &large_list, 0).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("exact_list", size), &config, |b, config| {
+            b.iter(|| Game::new(black_box(config), &exact_list, 0).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, create_board);
+criterion_main!(benches);
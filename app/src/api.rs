@@ -0,0 +1,562 @@
+use actix_web::{
+    http::{header, StatusCode},
+    web, HttpMessage, HttpRequest, HttpResponse,
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::ClientSession,
+    database::Database,
+    event::{CloseRoom, ErrorCode},
+    game::{Game, GameStatus},
+    history::{replay, Move},
+    server::{Room, ServerError},
+    words::{validate_custom_words, MAX_CUSTOM_WORDS, MAX_WORD_LENGTH},
+    AppData,
+};
+
+/// Largest word list upload accepted, in bytes. Leaves room for a full list of
+/// the longest allowed words plus separators.
+const MAX_UPLOAD_BYTES: usize = MAX_CUSTOM_WORDS * (MAX_WORD_LENGTH + 8);
+
+pub fn configure<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/rooms").route(web::get().to(list_rooms::<T>)));
+    cfg.service(
+        web::resource("/rooms/{name}")
+            .route(web::get().to(get_room::<T>))
+            .route(web::delete().to(delete_room::<T>))
+    );
+    cfg.service(web::resource("/rooms/{name}/players").route(web::get().to(list_players::<T>)));
+    cfg.service(
+        web::resource("/rooms/{name}/words")
+            .app_data(web::PayloadConfig::new(MAX_UPLOAD_BYTES))
+            .route(web::post().to(upload_words::<T>))
+    );
+    cfg.service(web::resource("/games/{id}").route(web::get().to(export_game::<T>)));
+    cfg.service(web::resource("/games/{id}/replay").route(web::get().to(replay_game::<T>)));
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ApiError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(code: ErrorCode, message: String) -> ApiError {
+        ApiError { code, message }
+    }
+
+    fn response(&self) -> HttpResponse {
+        let status = match self.code {
+            ErrorCode::RoomNotFound | ErrorCode::GameNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::GameNotFinished => StatusCode::CONFLICT,
+            ErrorCode::NotAdmin => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotHost => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        HttpResponse::build(status).json(self)
+    }
+}
+
+impl From<ErrorCode> for ApiError {
+    fn from(code: ErrorCode) -> Self {
+        ApiError::new(code.clone(), code.message().to_string())
+    }
+}
+
+/// What the lobby needs to know about a room without joining it. Private rooms
+/// only show their counts, unless the admin asks.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomSummary {
+    pub name: String,
+    pub player_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_id: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_status: Option<GameStatus>,
+    pub remaining_cards: Vec<u8>,
+    pub private: bool,
+    pub locked: bool,
+}
+
+impl RoomSummary {
+    pub fn new<T: Database>(database: &T, room: Room) -> anyhow::Result<RoomSummary> {
+        let game = database.get_game(room.game_id)?;
+        Ok(RoomSummary {
+            player_count: room.sessions.len(),
+            game_id: Some(room.game_id),
+            game_status: Some(game.game_status),
+            remaining_cards: game.remaining_cards,
+            private: room.password_hash.is_some(),
+            locked: room.locked,
+            name: room.name,
+        })
+    }
+
+    /// Drops what a private room's game is, for anyone but its players and the admin.
+    fn hide_private(self) -> RoomSummary {
+        if !self.private {
+            return self;
+        }
+        RoomSummary { game_id: None, game_status: None, ..self }
+    }
+}
+
+/// Summarizes a room for the request, hiding private rooms' details unless it
+/// carries the admin token.
+fn summarize_room<T: 'static + Database + std::marker::Unpin>(req: &HttpRequest, data: &AppData<T>, room: Room) -> anyhow::Result<RoomSummary> {
+    let summary = RoomSummary::new(&data.database, room)?;
+    Ok(if check_admin(req, data).is_ok() { summary } else { summary.hide_private() })
+}
+
+async fn list_rooms<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    req: HttpRequest,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    let summaries: anyhow::Result<Vec<RoomSummary>> = data.database
+        .get_rooms()
+        .and_then(|rooms| rooms.into_iter().map(|room| summarize_room(&req, &data, room)).collect());
+    match summaries {
+        Ok(mut summaries) => {
+            summaries.sort_by(|a, b| a.name.cmp(&b.name));
+            HttpResponse::Ok().json(summaries)
+        },
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn get_room<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    let room = match data.database.get_room(&path.into_inner()) {
+        Ok(room) => room,
+        Err(e) => return ApiError::new(ErrorCode::RoomNotFound, e.to_string()).response(),
+    };
+    match summarize_room(&req, &data, room) {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Checks the request carries the admin token as a bearer token. Without a
+/// configured token, every admin request is turned away.
+fn check_admin<T: 'static + Database + std::marker::Unpin>(req: &HttpRequest, data: &AppData<T>) -> Result<(), ApiError> {
+    match (&data.admin_token, bearer_token(req)) {
+        (Some(admin_token), Some(token)) if admin_token == token => Ok(()),
+        _ => Err(ErrorCode::NotAdmin.into()),
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Checks the request comes from the room's host, who proves it with the resume
+/// token the server sent them as a bearer token. The admin token works for any room.
+fn check_host<T: 'static + Database + std::marker::Unpin>(req: &HttpRequest, data: &AppData<T>, room: &Room) -> Result<(), ApiError> {
+    if check_admin(req, data).is_ok() {
+        return Ok(());
+    }
+    let session = bearer_token(req).and_then(|token| data.database.get_session_by_resume_token(token).ok());
+    match session {
+        Some(session) if session.room == room.name && room.current_host() == Some(session.id) => Ok(()),
+        _ => Err(ErrorCode::NotHost.into()),
+    }
+}
+
+/// Disconnects everyone in the room and deletes it. Needs the admin token.
+async fn delete_room<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    if let Err(e) = check_admin(&req, &data) {
+        return e.response();
+    }
+    let room = path.into_inner();
+    match data.server.send(CloseRoom { room: room.clone() }).await {
+        Ok(Ok(())) => HttpResponse::NoContent().finish(),
+        Ok(Err(ServerError::RoomNotFound(_))) => ApiError::new(ErrorCode::RoomNotFound, format!("There is no room named '{}'.", room)).response(),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// The players in a room, in the order they joined. Private rooms' players are
+/// only listed for the admin.
+async fn list_players<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    let room = match data.database.get_room(&path.into_inner()) {
+        Ok(room) => room,
+        Err(e) => return ApiError::new(ErrorCode::RoomNotFound, e.to_string()).response(),
+    };
+    if room.password_hash.is_some() {
+        if let Err(e) = check_admin(&req, &data) {
+            return e.response();
+        }
+    }
+    let players: anyhow::Result<Vec<ClientSession>> = room.sessions
+        .iter()
+        .map(|id| data.database.get_session(id))
+        .collect();
+    match players {
+        Ok(players) => HttpResponse::Ok().json(players),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Word lists can be uploaded as a JSON array, a JSON object with a `words`
+/// array, or plain text with one word per line.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum UploadedWords {
+    List(Vec<String>),
+    Object { words: Vec<String> },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UploadWordsResponse {
+    room: String,
+    word_count: usize,
+}
+
+fn parse_uploaded_words(req: &HttpRequest, body: &[u8]) -> Result<Vec<String>, String> {
+    let text = std::str::from_utf8(body).map_err(|_| String::from("Word lists must be UTF-8 text."))?;
+    if req.content_type() == "application/json" {
+        return match serde_json::from_str(text) {
+            Ok(UploadedWords::List(words)) | Ok(UploadedWords::Object { words }) => Ok(words),
+            Err(e) => Err(format!("Could not parse word list: {}", e)),
+        };
+    }
+    Ok(text.lines().map(String::from).collect())
+}
+
+async fn upload_words<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    path: web::Path<String>,
+    req: HttpRequest,
+    body: web::Bytes,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    let room_name = path.into_inner();
+    let mut database = data.database.clone();
+
+    let room = match database.get_room(&room_name) {
+        Ok(room) => room,
+        Err(e) => return ApiError::new(ErrorCode::RoomNotFound, e.to_string()).response(),
+    };
+    if let Err(e) = check_host(&req, &data, &room) {
+        return e.response();
+    }
+    let card_count = match database.get_game(room.game_id) {
+        Ok(game) => game.config.card_count(),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    let words = match parse_uploaded_words(&req, &body)
+        .and_then(|words| validate_custom_words(words, card_count).map_err(|e| e.to_string()))
+    {
+        Ok(words) => words,
+        Err(message) => return ApiError::new(ErrorCode::InvalidWordList, message).response(),
+    };
+
+    if let Err(e) = database.set_room_words(&room_name, &words) {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    debug!("Uploaded {} words to room {}.", words.len(), room_name);
+
+    HttpResponse::Ok().json(UploadWordsResponse {
+        room: room_name,
+        word_count: words.len(),
+    })
+}
+
+/// A finished game along with every move that led to it.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GameExport {
+    id: usize,
+    game: Game,
+    moves: Vec<Move>,
+}
+
+/// Loads a game for exporting. Exports show the whole key, so games that are still
+/// being played can't be exported.
+fn finished_game<T: Database>(database: &T, game_id: usize) -> Result<Game, ApiError> {
+    let game = database
+        .get_game(game_id)
+        .map_err(|e| ApiError::new(ErrorCode::GameNotFound, e.to_string()))?;
+    if game.game_status.is_over() {
+        Ok(game)
+    } else {
+        Err(ErrorCode::GameNotFinished.into())
+    }
+}
+
+async fn export_game<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    path: web::Path<usize>,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    let game_id = path.into_inner();
+    let game = match finished_game(&data.database, game_id) {
+        Ok(game) => game,
+        Err(e) => return e.response(),
+    };
+    match data.database.get_moves(game_id) {
+        Ok(moves) => HttpResponse::Ok().json(GameExport { id: game_id, game, moves }),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Every move of a finished game, each with the game as it was right after the move.
+async fn replay_game<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    path: web::Path<usize>,
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    let game_id = path.into_inner();
+    if let Err(e) = finished_game(&data.database, game_id) {
+        return e.response();
+    }
+    match data.database.get_moves(game_id).and_then(|moves| replay(&moves)) {
+        Ok(steps) => HttpResponse::Ok().json(steps),
+        Err(e) => HttpResponse::InternalServerError().body(format!("{:#}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix::Actor;
+    use actix_web::{http::{header, StatusCode}, test, web, App};
+
+    use crate::{
+        client::{ClientSession, Heartbeat},
+        database::{Database, MemoryDatabase},
+        game::{CardType, Game, GameConfig, GameStatus},
+        history::{Move, MoveAction, ReplayStep},
+        server::WsServer,
+        words::WordLists,
+        AppData,
+    };
+
+    use super::RoomSummary;
+
+    #[actix_web::test]
+    async fn deletes_rooms_with_admin_token() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        let (session_id, _) = database.create_session("foo").unwrap();
+        let app_data = web::Data::new(AppData {
+            server: WsServer::new(database.clone(), WordLists::builtin()).start(),
+            database: database.clone(),
+            heartbeat: Heartbeat::default(),
+            admin_token: Some(String::from("secret")),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_data)
+                .service(web::scope("/api").configure(super::configure::<MemoryDatabase>))
+        ).await;
+
+        let req = test::TestRequest::delete().uri("/api/rooms/foo").to_request();
+        assert_eq!(StatusCode::UNAUTHORIZED, test::call_service(&app, req).await.status());
+        let req = test::TestRequest::delete()
+            .uri("/api/rooms/foo")
+            .insert_header(("Authorization", "Bearer wrong"))
+            .to_request();
+        assert_eq!(StatusCode::UNAUTHORIZED, test::call_service(&app, req).await.status());
+        assert!(database.get_room("foo").is_ok());
+
+        let req = test::TestRequest::delete()
+            .uri("/api/rooms/foo")
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_request();
+        assert_eq!(StatusCode::NO_CONTENT, test::call_service(&app, req).await.status());
+        assert!(database.get_room("foo").is_err());
+        assert!(database.get_session(&session_id).is_err());
+
+        let req = test::TestRequest::delete()
+            .uri("/api/rooms/foo")
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_request();
+        assert_eq!(StatusCode::NOT_FOUND, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn lists_rooms_and_players() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        database.create_room("bar", &game).unwrap();
+        database.set_room_password("bar", Some("hash")).unwrap();
+        let (session_id, _) = database.create_session("foo").unwrap();
+        let app_data = web::Data::new(AppData {
+            server: WsServer::new(database.clone(), WordLists::builtin()).start(),
+            database: database.clone(),
+            heartbeat: Heartbeat::default(),
+            admin_token: Some(String::from("secret")),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_data)
+                .service(web::scope("/api").configure(super::configure::<MemoryDatabase>))
+        ).await;
+
+        let req = test::TestRequest::get().uri("/api/rooms").to_request();
+        let rooms: Vec<RoomSummary> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(vec!["bar", "foo"], rooms.iter().map(|room| room.name.as_str()).collect::<Vec<&str>>());
+
+        let req = test::TestRequest::get().uri("/api/rooms/foo").to_request();
+        let room: RoomSummary = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(RoomSummary {
+            name: String::from("foo"),
+            player_count: 1,
+            game_id: Some(database.get_room("foo").unwrap().game_id),
+            game_status: Some(GameStatus::PLAYING {}),
+            remaining_cards: game.remaining_cards.clone(),
+            private: false,
+            locked: false,
+        }, room);
+
+        let req = test::TestRequest::get().uri("/api/rooms/foo/players").to_request();
+        let players: Vec<ClientSession> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(vec![database.get_session(&session_id).unwrap()], players);
+
+        // Private rooms only show their counts, except to the admin
+        assert_eq!((None, None, true), (rooms[0].game_id, rooms[0].game_status.clone(), rooms[0].private));
+        let req = test::TestRequest::get().uri("/api/rooms/bar").to_request();
+        let room: RoomSummary = test::call_and_read_body_json(&app, req).await;
+        assert_eq!((None, None, game.remaining_cards), (room.game_id, room.game_status, room.remaining_cards));
+        let admin = (header::AUTHORIZATION, "Bearer secret");
+        let req = test::TestRequest::get().uri("/api/rooms/bar").insert_header(admin.clone()).to_request();
+        let room: RoomSummary = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(Some(database.get_room("bar").unwrap().game_id), room.game_id);
+
+        let req = test::TestRequest::get().uri("/api/rooms/bar/players").to_request();
+        assert_eq!(StatusCode::UNAUTHORIZED, test::call_service(&app, req).await.status());
+        let req = test::TestRequest::get().uri("/api/rooms/bar/players").insert_header(admin).to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+
+        let req = test::TestRequest::get().uri("/api/rooms/baz").to_request();
+        assert_eq!(StatusCode::NOT_FOUND, test::call_service(&app, req).await.status());
+        let req = test::TestRequest::get().uri("/api/rooms/baz/players").to_request();
+        assert_eq!(StatusCode::NOT_FOUND, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn uploads_words() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        let (host_id, host_token) = database.create_session("foo").unwrap();
+        let (_, player_token) = database.create_session("foo").unwrap();
+        database.set_room_host("foo", Some(host_id)).unwrap();
+        let app_data = web::Data::new(AppData {
+            server: WsServer::new(database.clone(), WordLists::builtin()).start(),
+            database: database.clone(),
+            heartbeat: Heartbeat::default(),
+            admin_token: None,
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_data)
+                .service(web::scope("/api").configure(super::configure::<MemoryDatabase>))
+        ).await;
+        let host = (header::AUTHORIZATION, format!("Bearer {}", host_token));
+
+        // Only the host can change the words
+        let words: Vec<String> = (0..30).map(|i| format!("word{}", i)).collect();
+        let req = test::TestRequest::post()
+            .uri("/api/rooms/foo/words")
+            .set_payload(words.join("\n"))
+            .to_request();
+        assert_eq!(StatusCode::FORBIDDEN, test::call_service(&app, req).await.status());
+        let req = test::TestRequest::post()
+            .uri("/api/rooms/foo/words")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", player_token)))
+            .set_payload(words.join("\n"))
+            .to_request();
+        assert_eq!(StatusCode::FORBIDDEN, test::call_service(&app, req).await.status());
+        assert_eq!(None, database.get_room_words("foo").unwrap());
+
+        let req = test::TestRequest::post()
+            .uri("/api/rooms/foo/words")
+            .insert_header(host.clone())
+            .set_payload(words.join("\n"))
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        assert_eq!(30, database.get_room_words("foo").unwrap().unwrap().len());
+
+        let req = test::TestRequest::post()
+            .uri("/api/rooms/foo/words")
+            .insert_header(host.clone())
+            .set_json(serde_json::json!({ "words": words[..25] }))
+            .to_request();
+        assert_eq!(StatusCode::OK, test::call_service(&app, req).await.status());
+        assert_eq!(Some(String::from("WORD0")), database.get_room_words("foo").unwrap().unwrap().first().cloned());
+        assert_eq!(25, database.get_room_words("foo").unwrap().unwrap().len());
+
+        let req = test::TestRequest::post()
+            .uri("/api/rooms/foo/words")
+            .insert_header(host.clone())
+            .set_json(&words[..24])
+            .to_request();
+        assert_eq!(StatusCode::BAD_REQUEST, test::call_service(&app, req).await.status());
+
+        let req = test::TestRequest::post()
+            .uri("/api/rooms/bar/words")
+            .set_payload(words.join("\n"))
+            .to_request();
+        assert_eq!(StatusCode::NOT_FOUND, test::call_service(&app, req).await.status());
+    }
+
+    #[actix_web::test]
+    async fn exports_finished_games() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        let game_id = database.get_room("foo").unwrap().game_id;
+        database.log_move(game_id, &Move::new(1, MoveAction::NewGame { game: game.clone() })).unwrap();
+        let app_data = web::Data::new(AppData {
+            server: WsServer::new(database.clone(), WordLists::builtin()).start(),
+            database: database.clone(),
+            heartbeat: Heartbeat::default(),
+            admin_token: None,
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(app_data)
+                .service(web::scope("/api").configure(super::configure::<MemoryDatabase>))
+        ).await;
+
+        let req = test::TestRequest::get().uri(&format!("/api/games/{}", game_id)).to_request();
+        assert_eq!(StatusCode::CONFLICT, test::call_service(&app, req).await.status());
+
+        let assassin = game.board.iter().flatten().find(|card| card.card_type == CardType::ASSASSIN).unwrap().coord;
+        database.flip_card(game_id, assassin).unwrap();
+        database.log_move(game_id, &Move::new(1, MoveAction::FlipCard { coord: assassin })).unwrap();
+
+        let req = test::TestRequest::get().uri(&format!("/api/games/{}", game_id)).to_request();
+        let export: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(2, export["moves"].as_array().unwrap().len());
+
+        let req = test::TestRequest::get().uri(&format!("/api/games/{}/replay", game_id)).to_request();
+        let steps: Vec<ReplayStep> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(database.get_game(game_id).unwrap(), steps[1].game);
+
+        let req = test::TestRequest::get().uri(&format!("/api/games/{}", game_id + 1)).to_request();
+        assert_eq!(StatusCode::NOT_FOUND, test::call_service(&app, req).await.status());
+    }
+}
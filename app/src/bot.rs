@@ -0,0 +1,466 @@
+use std::{sync::Arc, time::Duration};
+
+use actix::{Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner, Handler, Running, WrapFuture};
+use actix_web::rt::task;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{ClientAddr, ClientSession},
+    database::Database,
+    embeddings::{similarity, Embeddings},
+    event::{ClientControl, ClientDisconnected, ClientRequest, ClientRequestType, Event, EventMessage},
+    game::{CardType, CardView, Clue, GameMode, GameStatus, GameView, Team},
+    server::WsServer,
+};
+
+/// What a bot does for its team.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum BotRole {
+    /// Gives clues.
+    #[default]
+    Spymaster,
+    /// Flips cards for the team's clues.
+    Operative,
+}
+
+/// How careful the spymaster bot is when it picks a clue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClueSettings {
+    /// The most cards a single clue is meant to lead to.
+    pub max_count: usize,
+    /// How similar a clue has to be to a card for it to count as leading to it.
+    pub min_similarity: f32,
+    /// How much closer the clue has to be to each of its cards than to the
+    /// other team's cards, bystanders and the assassin.
+    pub opponent_margin: f32,
+    pub bystander_margin: f32,
+    pub assassin_margin: f32,
+}
+
+impl Default for ClueSettings {
+    fn default() -> Self {
+        ClueSettings {
+            max_count: 3,
+            min_similarity: 0.25,
+            opponent_margin: 0.05,
+            bystander_margin: 0.0,
+            assassin_margin: 0.1,
+        }
+    }
+}
+
+/// A clue the spymaster bot came up with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClueChoice {
+    pub word: String,
+    pub count: u8,
+    /// The cards the clue is meant to lead to, closest first.
+    pub targets: Vec<String>,
+}
+
+/// Whether `word` could be given as a clue on a board with these unflipped words.
+/// Besides not being on the board, clues can't contain or be part of a board
+/// word, so `FIREMAN` can't lead to `FIRE`.
+fn is_valid_clue(word: &str, board_words: &[String]) -> bool {
+    word.chars().all(char::is_alphabetic)
+        && !board_words.iter().any(|board_word| board_word.contains(word) || word.contains(board_word.as_str()))
+}
+
+/// Picks the clue for `team`'s unflipped cards that leads to the most of them
+/// while staying clear of every card they shouldn't flip. The board has to show
+/// the key, as a spymaster sees it. When nothing is safe, the clue leads to the
+/// single card that is furthest from trouble. Returns `None` when no card of the
+/// team is in the word vectors.
+pub fn choose_clue(embeddings: &Embeddings, board: &[Vec<CardView>], team: &Team, settings: &ClueSettings) -> Option<ClueChoice> {
+    let team_card = CardType::from_team(team);
+    let unflipped: Vec<&CardView> = board.iter().flatten().filter(|card| !card.flipped).collect();
+    let board_words: Vec<String> = unflipped.iter().map(|card| card.word.to_uppercase()).collect();
+    let targets: Vec<(&str, Vec<f32>)> = unflipped.iter()
+        .filter(|card| card.card_type.as_ref() == Some(&team_card))
+        .filter_map(|card| Some((card.word.as_str(), embeddings.vector(&card.word)?)))
+        .collect();
+    let avoid: Vec<(Vec<f32>, f32)> = unflipped.iter()
+        .filter(|card| card.card_type.as_ref() != Some(&team_card))
+        .filter_map(|card| {
+            let margin = match card.card_type {
+                Some(CardType::ASSASSIN) => settings.assassin_margin,
+                Some(CardType::BYSTANDER) => settings.bystander_margin,
+                _ => settings.opponent_margin,
+            };
+            Some((embeddings.vector(&card.word)?, margin))
+        })
+        .collect();
+    if targets.is_empty() {
+        return None;
+    }
+
+    // The best safe clue by how many cards it leads to and then by how far its
+    // weakest card is from danger, and the fallback for when nothing is safe.
+    let mut best: Option<(f32, &str, Vec<usize>)> = None;
+    let mut fallback: Option<(f32, &str, usize)> = None;
+    for (word, vector) in embeddings.words() {
+        if !is_valid_clue(word, &board_words) {
+            continue;
+        }
+        let danger = avoid.iter()
+            .map(|(card, margin)| similarity(vector, card) + margin)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mut similarities: Vec<(usize, f32)> = targets.iter()
+            .enumerate()
+            .map(|(i, (_, card))| (i, similarity(vector, card)))
+            .collect();
+        similarities.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let (closest, closest_similarity) = similarities[0];
+        if fallback.as_ref().is_none_or(|(score, _, _)| closest_similarity - danger > *score) {
+            fallback = Some((closest_similarity - danger, word, closest));
+        }
+        for count in 1..=settings.max_count.min(similarities.len()) {
+            let weakest = similarities[count - 1].1;
+            if weakest < settings.min_similarity || weakest <= danger {
+                break;
+            }
+            let score = count as f32 + weakest - danger;
+            if best.as_ref().is_none_or(|(best_score, _, _)| score > *best_score) {
+                best = Some((score, word, similarities[..count].iter().map(|(i, _)| *i).collect()));
+            }
+        }
+    }
+
+    let (word, cards) = match (best, fallback) {
+        (Some((_, word, cards)), _) => (word, cards),
+        (None, Some((_, word, card))) => (word, vec![card]),
+        (None, None) => return None,
+    };
+    Some(ClueChoice {
+        word: word.to_string(),
+        count: cards.len() as u8,
+        targets: cards.iter().map(|&i| targets[i].0.to_string()).collect(),
+    })
+}
+
+/// How sure the operative bot has to be to keep guessing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuessSettings {
+    /// How similar a card has to be to the clue for the bot to flip it, after
+    /// the first guess of the turn.
+    pub min_similarity: f32,
+}
+
+impl Default for GuessSettings {
+    fn default() -> Self {
+        GuessSettings { min_similarity: 0.3 }
+    }
+}
+
+/// A card the operative bot wants to flip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Guess {
+    pub coord: (usize, usize),
+    pub word: String,
+    pub similarity: f32,
+}
+
+/// Picks the unflipped card closest to the clue, only looking at the words since
+/// the board is the one operatives see. The first guess of a turn is always
+/// made, after that the bot stops once it has made as many guesses as the clue
+/// was for or once no card is close enough to the clue. Returns `None` when the
+/// bot should end the turn, which it also does for clues it has no vector for.
+pub fn choose_guess(
+    embeddings: &Embeddings,
+    board: &[Vec<CardView>],
+    clue: &Clue,
+    guesses_made: u8,
+    settings: &GuessSettings,
+) -> Option<Guess> {
+    if guesses_made > 0 && guesses_made >= clue.count {
+        return None;
+    }
+    let clue_vector = embeddings.vector(&clue.word)?;
+    let guess = board.iter()
+        .flatten()
+        .filter(|card| !card.flipped)
+        .filter_map(|card| Some(Guess {
+            coord: card.coord,
+            word: card.word.clone(),
+            similarity: similarity(&clue_vector, &embeddings.vector(&card.word)?),
+        }))
+        .max_by(|a, b| a.similarity.total_cmp(&b.similarity))?;
+    (guesses_made == 0 || guess.similarity >= settings.min_similarity).then_some(guess)
+}
+
+/// Whether it's up to `session` to give a clue. The view has to show the key,
+/// which it doesn't until the server has made the session a spymaster.
+fn should_give_clue(session: &ClientSession, game: &GameView) -> bool {
+    session.is_spymaster
+        && session.team.as_ref() == Some(&game.turn_team)
+        && game.mode == GameMode::Classic
+        && matches!(game.game_status, GameStatus::PLAYING {})
+        && game.guesses_remaining.is_none()
+        && game.board.iter().flatten().all(|card| card.card_type.is_some())
+}
+
+/// Whether it's up to `session` to flip a card, which it is once its team has a clue.
+fn should_guess(session: &ClientSession, game: &GameView) -> bool {
+    !session.is_spymaster
+        && session.team.as_ref() == Some(&game.turn_team)
+        && game.mode == GameMode::Classic
+        && matches!(game.game_status, GameStatus::PLAYING {})
+        && game.guesses_remaining.is_some()
+}
+
+/// How long the operative bot waits before each flip, so players can follow along.
+const GUESS_DELAY: Duration = Duration::from_millis(750);
+
+/// A spymaster or operative that plays in a room like any other player. The
+/// server gives it a session on a team, and it answers the events it is sent
+/// with the same requests a player's websocket would send.
+pub struct Bot<T: 'static + Database + std::marker::Unpin> {
+    server: Addr<WsServer<T>>,
+    room_name: String,
+    embeddings: Arc<Embeddings>,
+    role: BotRole,
+    /// Set once the server sends the bot its session.
+    session: Option<ClientSession>,
+    /// The last state of the game the bot was sent, and the id of that game.
+    game: Option<GameView>,
+    game_id: Option<usize>,
+    /// Whether the bot is busy coming up with a clue or waiting to guess.
+    thinking: bool,
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Bot<T> {
+    pub fn new(server: Addr<WsServer<T>>, room: &str, embeddings: Arc<Embeddings>, role: BotRole) -> Self {
+        Bot {
+            server,
+            room_name: room.to_string(),
+            embeddings,
+            role,
+            session: None,
+            game: None,
+            game_id: None,
+            thinking: false,
+        }
+    }
+
+    fn send_request(&self, session_id: usize, request: ClientRequestType) {
+        self.server.do_send(ClientRequest {
+            sender_id: session_id,
+            room_name: self.room_name.clone(),
+            request,
+        });
+    }
+
+    /// Scores clues on a blocking thread, since going through every word vector
+    /// takes too long for the server's thread that the bot runs on.
+    fn give_clue(&mut self, session: ClientSession, game: GameView, ctx: &mut Context<Self>) {
+        self.thinking = true;
+        let embeddings = self.embeddings.clone();
+        let (game_id, clues) = (self.game_id, game.clues.len());
+        task::spawn_blocking(move || choose_clue(&embeddings, &game.board, &game.turn_team, &ClueSettings::default()))
+            .into_actor(self)
+            .map(move |clue, act, _ctx| {
+                act.thinking = false;
+                // The game may have moved on while the bot was thinking
+                let still_waiting = match (&act.session, &act.game) {
+                    (Some(session), Some(game)) => {
+                        act.game_id == game_id && game.clues.len() == clues && should_give_clue(session, game)
+                    }
+                    _ => false,
+                };
+                if !still_waiting {
+                    return;
+                }
+                match clue {
+                    Ok(Some(clue)) => {
+                        info!("Bot {} in room {} gives {} {} for {:?}.", session.id, act.room_name, clue.word, clue.count, clue.targets);
+                        act.send_request(session.id, ClientRequestType::GiveClue { word: clue.word, count: clue.count });
+                    }
+                    Ok(None) => {
+                        warn!("Bot {} in room {} has no word vectors for its cards.", session.id, act.room_name);
+                        let text = String::from("I don't know any of our words, someone else will have to give the clue.");
+                        act.send_request(session.id, ClientRequestType::Message { text });
+                    }
+                    Err(e) => warn!("Bot {} could not choose a clue: {}", session.id, e),
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Flips the card closest to the clue after a pause, or ends the turn. The
+    /// guess is made from the game as it is once the pause is over.
+    fn guess(&mut self, ctx: &mut Context<Self>) {
+        self.thinking = true;
+        ctx.run_later(GUESS_DELAY, |act, _ctx| {
+            act.thinking = false;
+            let (Some(session), Some(game)) = (&act.session, &act.game) else { return };
+            let (Some(clue), Some(guesses_remaining)) = (game.clues.last(), game.guesses_remaining) else { return };
+            if !should_guess(session, game) {
+                return;
+            }
+            let guesses_made = clue.count.saturating_add(1).saturating_sub(guesses_remaining);
+            let request = match choose_guess(&act.embeddings, &game.board, clue, guesses_made, &GuessSettings::default()) {
+                Some(guess) => {
+                    debug!("Bot {} in room {} guesses {} for {}.", session.id, act.room_name, guess.word, clue.word);
+                    ClientRequestType::FlipCard { coord: guess.coord }
+                }
+                None => ClientRequestType::NextTurn {},
+            };
+            act.send_request(session.id, request);
+        });
+    }
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Actor for Bot<T> {
+    type Context = Context<Self>;
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        if let Some(session) = &self.session {
+            self.server.do_send(ClientDisconnected {
+                session_id: session.id,
+                room: self.room_name.clone(),
+                addr: ClientAddr::new(ctx.address()),
+                timed_out: false,
+            });
+        }
+        Running::Stop
+    }
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Handler<EventMessage> for Bot<T> {
+    type Result = ();
+
+    fn handle(&mut self, event_message: EventMessage, ctx: &mut Self::Context) -> Self::Result {
+        match event_message.event {
+            Event::UpdateClientSession { session } => {
+                // Everyone stops being a spymaster when a new game starts
+                let spymaster = self.role == BotRole::Spymaster;
+                if session.is_spymaster != spymaster {
+                    self.send_request(session.id, ClientRequestType::SetSpyMaster { spymaster });
+                }
+                self.session = Some(session);
+            }
+            Event::GameStateUpdate { game_id, game } => {
+                self.game = Some(game.clone());
+                self.game_id = Some(game_id);
+                match &self.session {
+                    Some(session) if !self.thinking && should_give_clue(session, &game) => {
+                        self.give_clue(session.clone(), game, ctx);
+                    }
+                    Some(session) if !self.thinking && should_guess(session, &game) => self.guess(ctx),
+                    _ => {}
+                }
+            }
+            Event::Error { code, message } => debug!("Bot in room {} was told {:?}: {}", self.room_name, code, message),
+            _ => {}
+        }
+    }
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Handler<ClientControl> for Bot<T> {
+    type Result = ();
+
+    fn handle(&mut self, control: ClientControl, ctx: &mut Self::Context) -> Self::Result {
+        match control {
+            ClientControl::Reassign { session_id } => {
+                if let Some(session) = self.session.as_mut() {
+                    session.id = session_id;
+                }
+            }
+            ClientControl::Replaced | ClientControl::Kicked | ClientControl::RoomClosed => ctx.stop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        embeddings::Embeddings,
+        game::{CardType, CardView, Clue, Team},
+    };
+
+    use super::{choose_clue, choose_guess, is_valid_clue, ClueChoice, ClueSettings, GuessSettings};
+
+    fn card(word: &str, card_type: CardType, flipped: bool) -> CardView {
+        CardView { word: word.to_string(), card_type: Some(card_type), flipped, coord: (0, 0), bystander_for: Vec::new() }
+    }
+
+    #[test]
+    fn chooses_clues() {
+        // Three dimensions: fruit, animals and the sea
+        let embeddings = Embeddings::parse(concat!(
+            "apple 1 0 0\n", "cherry 0.9 0.2 0\n", "lemon 0.9 0 0.3\n", "tiger 0 1 0\n", "shark 0 0.6 0.8\n",
+            "ocean 0 0 1\n", "fruit 0.98 0.05 0.05\n", "animal 0.05 1 0.1\n", "fish 0 0.7 0.7\n", "apples 1 0 0\n",
+        ).as_bytes(), 100).unwrap();
+        let mut board = vec![vec![
+            card("APPLE", CardType::RED, false),
+            card("CHERRY", CardType::RED, false),
+            card("LEMON", CardType::RED, false),
+            card("TIGER", CardType::BLUE, false),
+            card("SHARK", CardType::ASSASSIN, false),
+            card("OCEAN", CardType::BYSTANDER, false),
+        ]];
+
+        let settings = ClueSettings::default();
+        assert_eq!(Some(ClueChoice {
+            word: String::from("FRUIT"),
+            count: 3,
+            targets: vec![String::from("APPLE"), String::from("CHERRY"), String::from("LEMON")],
+        }), choose_clue(&embeddings, &board, &Team::RED, &settings));
+
+        // FISH is closest to the assassin, so blue is only safe with ANIMAL
+        let clue = choose_clue(&embeddings, &board, &Team::BLUE, &settings).unwrap();
+        assert_eq!(("ANIMAL", 1), (clue.word.as_str(), clue.count));
+
+        // Flipped cards don't need a clue anymore
+        board[0][0].flipped = true;
+        board[0][1].flipped = true;
+        let clue = choose_clue(&embeddings, &board, &Team::RED, &settings).unwrap();
+        assert_eq!(vec![String::from("LEMON")], clue.targets);
+
+        board[0][3] = CardView { card_type: None, ..card("UNKNOWN", CardType::BLUE, false) };
+        assert_eq!(None, choose_clue(&embeddings, &board, &Team::BLUE, &settings));
+    }
+
+    #[test]
+    fn chooses_guesses() {
+        let embeddings = Embeddings::parse(
+            "apple 1 0 0\ncherry 0.8 0.6 0\ntiger 0 1 0\nocean 0 0 1\nfruit 1 0.1 0\nwater 0 0.2 1\n".as_bytes(),
+            100,
+        ).unwrap();
+        // Operatives only see the words of unflipped cards
+        let card = |word: &str, coord: (usize, usize)| CardView {
+            word: word.to_string(), card_type: None, flipped: false, coord, bystander_for: Vec::new(),
+        };
+        let mut board = vec![vec![card("APPLE", (0, 0)), card("CHERRY", (0, 1)), card("TIGER", (0, 2)), card("OCEAN", (0, 3))]];
+        let clue = |word: &str, count| Clue { team: Team::RED, word: word.to_string(), count };
+        let settings = GuessSettings::default();
+
+        let guess = choose_guess(&embeddings, &board, &clue("fruit", 2), 0, &settings).unwrap();
+        assert_eq!(((0, 0), "APPLE"), (guess.coord, guess.word.as_str()));
+        board[0][0].flipped = true;
+        let guess = choose_guess(&embeddings, &board, &clue("fruit", 2), 1, &settings).unwrap();
+        assert_eq!((0, 1), guess.coord);
+        // The bot stops once it made as many guesses as the clue was for
+        assert_eq!(None, choose_guess(&embeddings, &board, &clue("fruit", 2), 2, &settings));
+
+        // ...or once nothing left is close enough, though the first guess is always made
+        board[0][1].flipped = true;
+        assert_eq!(None, choose_guess(&embeddings, &board, &clue("fruit", 3), 2, &settings));
+        assert_eq!((0, 2), choose_guess(&embeddings, &board, &clue("fruit", 3), 0, &settings).unwrap().coord);
+        assert_eq!((0, 3), choose_guess(&embeddings, &board, &clue("water", 1), 0, &settings).unwrap().coord);
+        assert_eq!(None, choose_guess(&embeddings, &board, &clue("unknown", 1), 0, &settings));
+    }
+
+    #[test]
+    fn rejects_clues_that_give_away_words() {
+        let board_words = vec![String::from("APPLE"), String::from("ICE CREAM")];
+        assert!(is_valid_clue("FRUIT", &board_words));
+        assert!(!is_valid_clue("APPLE", &board_words));
+        assert!(!is_valid_clue("APPLES", &board_words));
+        assert!(!is_valid_clue("ICE", &board_words));
+        assert!(!is_valid_clue("CAN'T", &board_words));
+    }
+}
@@ -0,0 +1,517 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::{
+    api::RoomSummary,
+    client::ClientSession,
+    config::{ConfigArgs, DatabaseSetting},
+    database::{Database, SqliteDatabase},
+    embeddings::Embeddings,
+    game::{CardType, Game, GameConfig, Team},
+    simulation::{self, EmbeddingOperative, EmbeddingSpymaster, Operative, RandomOperative, TeamStrategy},
+    words::{WordList, WordLists},
+};
+
+/// Codenames game server and tools for managing it.
+#[derive(Parser, Debug)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Server settings used when no command is given.
+    #[command(flatten)]
+    pub serve: ConfigArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs the game server, the default when no command is given.
+    Serve(ConfigArgs),
+    /// Lists, inspects and deletes rooms.
+    Rooms {
+        #[command(flatten)]
+        source: RoomSource,
+        #[command(subcommand)]
+        command: RoomsCommand,
+    },
+    /// Works with word lists.
+    Words {
+        #[command(subcommand)]
+        command: WordsCommand,
+    },
+    /// Works with game boards.
+    Board {
+        #[command(subcommand)]
+        command: BoardCommand,
+    },
+    /// Plays bots against each other and reports how they did.
+    Simulate(SimulateArgs),
+}
+
+/// Where the `rooms` commands find rooms. A running server is asked through its
+/// API unless a database is given, which is read directly.
+#[derive(Args, Debug)]
+pub struct RoomSource {
+    /// URL of a running server.
+    #[arg(long, env = "CODENAMES_SERVER_URL", default_value = "http://localhost:8080", global = true)]
+    pub server: String,
+    /// Token for the server's admin API, needed to delete rooms.
+    #[arg(long, env = "CODENAMES_ADMIN_TOKEN", global = true)]
+    pub admin_token: Option<String>,
+    /// Database to read directly instead of asking a server, as `sqlite:<path>`.
+    /// Only change it while the server is stopped.
+    #[arg(long, global = true)]
+    pub database: Option<DatabaseSetting>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RoomsCommand {
+    /// Lists every room.
+    List,
+    /// Shows a room and its players.
+    Inspect { name: String },
+    /// Disconnects everyone in a room and deletes it.
+    Delete { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum WordsCommand {
+    /// Checks a word list file, one word per line, can fill a board.
+    Validate {
+        file: PathBuf,
+        #[command(flatten)]
+        game_config: GameConfigArgs,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BoardCommand {
+    /// Deals a board and prints it.
+    Generate {
+        /// Seed to deal the board from, a random one is used if not given.
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Built-in word list to draw words from.
+        #[arg(long, default_value = WordLists::DEFAULT, conflicts_with = "words")]
+        word_list: String,
+        /// Word list file to draw words from instead, one word per line.
+        #[arg(long)]
+        words: Option<PathBuf>,
+        /// Also print which card is which.
+        #[arg(long)]
+        key: bool,
+        #[command(flatten)]
+        game_config: GameConfigArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct SimulateArgs {
+    /// Word vectors the bots play with, in the GloVe text format.
+    #[arg(long, env = "CODENAMES_EMBEDDINGS")]
+    pub embeddings: PathBuf,
+    /// How many words to read from the embeddings file. Every clue goes through
+    /// all of them, so fewer words make the simulation faster.
+    #[arg(long, default_value_t = 20_000)]
+    pub embedding_words: usize,
+    /// How many games to play.
+    #[arg(long, default_value_t = 1000)]
+    pub games: usize,
+    /// Seed of the first game, each game after it is dealt from the next seed.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Built-in word list to draw words from.
+    #[arg(long, default_value = WordLists::DEFAULT, conflicts_with = "words")]
+    pub word_list: String,
+    /// Word list file to draw words from instead, one word per line.
+    #[arg(long)]
+    pub words: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = OperativeKind::Embeddings)]
+    pub red_operative: OperativeKind,
+    #[arg(long, value_enum, default_value_t = OperativeKind::Embeddings)]
+    pub blue_operative: OperativeKind,
+    #[command(flatten)]
+    pub game_config: GameConfigArgs,
+}
+
+/// Who flips cards for a team in a simulation.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperativeKind {
+    /// The operative bot.
+    Embeddings,
+    /// Flips random cards.
+    Random,
+}
+
+#[derive(Args, Debug)]
+pub struct GameConfigArgs {
+    /// JSON file with the game config to check against, the standard 5x5 board if not given.
+    #[arg(long)]
+    pub game_config: Option<PathBuf>,
+}
+
+impl GameConfigArgs {
+    fn load(&self) -> Result<GameConfig> {
+        match &self.game_config {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .context(format!("Could not read game config '{}'.", path.display()))?;
+                serde_json::from_str(&text).context(format!("Could not parse game config '{}'.", path.display()))
+            },
+            None => Ok(GameConfig::default()),
+        }
+    }
+}
+
+/// Runs any command besides `serve`.
+pub fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Serve(_) => bail!("The server can't be started from here, run it from the binary."),
+        Command::Rooms { source, command } => run_rooms(source, command),
+        Command::Words { command: WordsCommand::Validate { file, game_config } } => {
+            let list = read_word_list(&file)?;
+            let config = game_config.load()?;
+            Game::new(&config, &list, 0).context(format!("'{}' can't be used for this board.", file.display()))?;
+            println!(
+                "'{}' has {} words and can fill a {}x{} board.",
+                list.name, list.words.len(), config.rows, config.cols
+            );
+            Ok(())
+        },
+        Command::Board { command: BoardCommand::Generate { seed, word_list, words, key, game_config } } => {
+            let list = find_word_list(&word_list, words.as_deref())?;
+            let seed = seed.unwrap_or_else(Game::random_seed);
+            let game = Game::new(&game_config.load()?, &list, seed)?;
+            println!("Seed {}, {:?} goes first.", seed, game.starting_team);
+            print!("{}", render_board(&game, key));
+            Ok(())
+        },
+        Command::Simulate(args) => run_simulation(args),
+    }
+}
+
+/// The word list file at `path` if there is one, or else the built-in list named `name`.
+fn find_word_list(name: &str, path: Option<&Path>) -> Result<WordList> {
+    match path {
+        Some(path) => read_word_list(path),
+        None => WordLists::builtin()
+            .get(name)
+            .cloned()
+            .context(format!("There is no built-in word list named '{}'.", name)),
+    }
+}
+
+fn run_simulation(args: SimulateArgs) -> Result<()> {
+    let list = find_word_list(&args.word_list, args.words.as_deref())?;
+    let config = args.game_config.load()?;
+    let embeddings = Embeddings::load(&args.embeddings, args.embedding_words)?;
+    let team = |kind: OperativeKind| {
+        let operative: Box<dyn Operative> = match kind {
+            OperativeKind::Embeddings => Box::new(EmbeddingOperative { embeddings: &embeddings, settings: Default::default() }),
+            OperativeKind::Random => Box::new(RandomOperative::new()),
+        };
+        TeamStrategy {
+            spymaster: Box::new(EmbeddingSpymaster { embeddings: &embeddings, settings: Default::default() }),
+            operative,
+        }
+    };
+
+    println!("Playing {} games from seed {} with {} word vectors.", args.games, args.seed, embeddings.len());
+    let report = simulation::simulate(
+        &config, &list, args.seed, args.games, &mut team(args.red_operative), &mut team(args.blue_operative),
+    )?;
+    print!("{}", report);
+    Ok(())
+}
+
+fn read_word_list(path: &Path) -> Result<WordList> {
+    let text = fs::read_to_string(path).context(format!("Could not read word list '{}'.", path.display()))?;
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("custom");
+    Ok(WordList::parse(name, &text))
+}
+
+fn card_marker(card_type: &CardType) -> &'static str {
+    match card_type {
+        CardType::RED => "R",
+        CardType::BLUE => "B",
+        CardType::GREEN => "G",
+        CardType::YELLOW => "Y",
+        CardType::AGENT => "A",
+        CardType::BYSTANDER => "-",
+        CardType::ASSASSIN => "X",
+    }
+}
+
+/// Lays the board out as a grid, marking each card with its type if `key` is set.
+/// Duet cards are marked with both sides of the key, red's side first.
+pub fn render_board(game: &Game, key: bool) -> String {
+    let width = game.board.iter().flatten().map(|card| card.word.chars().count()).max().unwrap_or(0);
+    let mut output = String::new();
+    for row in &game.board {
+        let cells: Vec<String> = row.iter()
+            .map(|card| {
+                let marker = match (key, &card.back_type) {
+                    (false, _) => String::new(),
+                    (true, None) => format!("{} ", card_marker(&card.card_type)),
+                    (true, Some(back_type)) => format!("{}{} ", card_marker(&card.card_type), card_marker(back_type)),
+                };
+                format!("{}{:<width$}", marker, card.word, width = width)
+            })
+            .collect();
+        output.push_str(cells.join("  ").trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+fn run_rooms(source: RoomSource, command: RoomsCommand) -> Result<()> {
+    match source.database {
+        Some(DatabaseSetting::Sqlite(path)) => {
+            let database = SqliteDatabase::open(path)?;
+            run_rooms_command(&mut DatabaseRooms(database), command)
+        },
+        Some(DatabaseSetting::Memory) => bail!("Rooms in memory are only reachable through the running server."),
+        None => run_rooms_command(&mut ServerRooms { url: source.server, admin_token: source.admin_token }, command),
+    }
+}
+
+fn run_rooms_command<R: RoomStore>(store: &mut R, command: RoomsCommand) -> Result<()> {
+    match command {
+        RoomsCommand::List => {
+            let rooms = store.list()?;
+            if rooms.is_empty() {
+                println!("There are no rooms.");
+            }
+            for room in rooms {
+                println!("{}", describe_room(&room));
+            }
+        },
+        RoomsCommand::Inspect { name } => {
+            let (room, players) = store.inspect(&name)?;
+            println!("{}", describe_room(&room));
+            if let (Some(game_id), Some(game_status)) = (room.game_id, &room.game_status) {
+                println!("Game {}, {:?}", game_id, game_status);
+            }
+            for player in players {
+                let team = player.team.map(|team| format!("{:?}", team)).unwrap_or_else(|| String::from("no team"));
+                let role = if player.is_spymaster { ", spymaster" } else { "" };
+                println!("  {} {} ({}{})", player.id, player.username, team, role);
+            }
+        },
+        RoomsCommand::Delete { name } => {
+            store.delete(&name)?;
+            println!("Deleted room {}.", name);
+        },
+    }
+    Ok(())
+}
+
+fn describe_room(room: &RoomSummary) -> String {
+    let mut flags = Vec::new();
+    if room.private {
+        flags.push("private");
+    }
+    if room.locked {
+        flags.push("locked");
+    }
+    let mut counts: Vec<String> = Team::ALL.iter()
+        .zip(&room.remaining_cards)
+        .map(|(team, count)| format!("{} {}", count, format!("{:?}", team).to_lowercase()))
+        .collect();
+    let last = counts.pop().unwrap_or_default();
+    let counts = if counts.is_empty() { last } else { format!("{} and {}", counts.join(", "), last) };
+    format!(
+        "{}: {} players, {} cards left{}",
+        room.name,
+        room.player_count,
+        counts,
+        if flags.is_empty() { String::new() } else { format!(" ({})", flags.join(", ")) },
+    )
+}
+
+/// Somewhere the `rooms` commands can find rooms.
+trait RoomStore {
+    fn list(&mut self) -> Result<Vec<RoomSummary>>;
+    fn inspect(&mut self, name: &str) -> Result<(RoomSummary, Vec<ClientSession>)>;
+    fn delete(&mut self, name: &str) -> Result<()>;
+}
+
+/// A running server, reached through its API.
+struct ServerRooms {
+    url: String,
+    admin_token: Option<String>,
+}
+
+/// The API path for a room. Room names come from the URL players joined with,
+/// so they can hold anything.
+fn room_path(name: &str) -> String {
+    format!("/rooms/{}", utf8_percent_encode(name, NON_ALPHANUMERIC))
+}
+
+impl ServerRooms {
+    fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}/api{}", self.url.trim_end_matches('/'), path);
+        let mut request = ureq::get(&url);
+        // Private rooms only show their games and players to the admin
+        if let Some(admin_token) = &self.admin_token {
+            request = request.set("Authorization", &format!("Bearer {}", admin_token));
+        }
+        request
+            .call()
+            .map_err(|e| describe_request_error(&url, e))?
+            .into_json()
+            .context(format!("Could not read the response from '{}'.", url))
+    }
+}
+
+impl RoomStore for ServerRooms {
+    fn list(&mut self) -> Result<Vec<RoomSummary>> {
+        self.get("/rooms")
+    }
+
+    fn inspect(&mut self, name: &str) -> Result<(RoomSummary, Vec<ClientSession>)> {
+        Ok((self.get(&room_path(name))?, self.get(&format!("{}/players", room_path(name)))?))
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        let url = format!("{}/api{}", self.url.trim_end_matches('/'), room_path(name));
+        let admin_token = self.admin_token.as_ref().context("Deleting rooms on a server needs --admin-token.")?;
+        ureq::delete(&url)
+            .set("Authorization", &format!("Bearer {}", admin_token))
+            .call()
+            .map_err(|e| describe_request_error(&url, e))?;
+        Ok(())
+    }
+}
+
+/// Turns a failed request into an error, using the server's message if it sent one.
+fn describe_request_error(url: &str, error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(status, response) => {
+            let message = response
+                .into_json::<serde_json::Value>()
+                .ok()
+                .and_then(|body| body.get("message").and_then(|message| message.as_str()).map(String::from))
+                .unwrap_or_else(|| format!("The server answered with status {}.", status));
+            anyhow::anyhow!(message)
+        },
+        error => anyhow::Error::new(error).context(format!("Could not reach the server at '{}'.", url)),
+    }
+}
+
+/// A persistent database, read directly.
+struct DatabaseRooms<T: Database>(T);
+
+impl<T: Database> RoomStore for DatabaseRooms<T> {
+    fn list(&mut self) -> Result<Vec<RoomSummary>> {
+        let mut rooms = self.0.get_rooms()?
+            .into_iter()
+            .map(|room| RoomSummary::new(&self.0, room))
+            .collect::<Result<Vec<RoomSummary>>>()?;
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(rooms)
+    }
+
+    fn inspect(&mut self, name: &str) -> Result<(RoomSummary, Vec<ClientSession>)> {
+        let room = self.0.get_room(name)?;
+        let players = room.sessions
+            .iter()
+            .map(|id| self.0.get_session(id))
+            .collect::<Result<Vec<ClientSession>>>()?;
+        Ok((RoomSummary::new(&self.0, room)?, players))
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        for session_id in self.0.get_room(name)?.sessions {
+            self.0.remove_session(session_id)?;
+        }
+        self.0.remove_room(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::{
+        database::{Database, MemoryDatabase},
+        game::{Game, GameConfig},
+        words::{WordList, WordLists},
+    };
+
+    use super::{render_board, room_path, Cli, Command, DatabaseRooms, OperativeKind, RoomStore, RoomsCommand};
+
+    #[test]
+    fn encodes_room_names() {
+        assert_eq!("/rooms/foo", room_path("foo"));
+        assert_eq!("/rooms/a%2Fb%3Fc%23d%20e", room_path("a/b?c#d e"));
+    }
+
+    #[test]
+    fn parses_commands() {
+        let cli = Cli::try_parse_from(["codenames", "--port", "9000"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(Some(9000), cli.serve.port);
+
+        let cli = Cli::try_parse_from(["codenames", "rooms", "delete", "foo", "--admin-token", "secret"]).unwrap();
+        let Some(Command::Rooms { source, command: RoomsCommand::Delete { name } }) = cli.command else {
+            panic!("Expected a rooms delete command.");
+        };
+        assert_eq!(("foo", Some(String::from("secret"))), (name.as_str(), source.admin_token));
+
+        assert!(Cli::try_parse_from(["codenames", "--port", "9000", "rooms", "list"]).is_err());
+
+        let cli = Cli::try_parse_from(["codenames", "simulate", "--embeddings", "glove.txt", "--blue-operative", "random"]).unwrap();
+        let Some(Command::Simulate(args)) = cli.command else {
+            panic!("Expected a simulate command.");
+        };
+        assert_eq!((OperativeKind::Embeddings, OperativeKind::Random), (args.red_operative, args.blue_operative));
+        assert_eq!(1000, args.games);
+    }
+
+    #[test]
+    fn renders_board() {
+        let config = GameConfig { rows: 1, cols: 3, starting_team_cards: 1, other_team_cards: 1, assassins: 1, bystanders: 0, ..GameConfig::default() };
+        let words = WordList::new("test", vec![String::from("A"), String::from("BB"), String::from("CCC")]);
+        let game = Game::new(&config, &words, 0).unwrap();
+
+        let board = render_board(&game, false);
+        assert_eq!(1, board.lines().count());
+        assert!(["A", "BB", "CCC"].iter().all(|word| board.contains(word)));
+        // Every column is as wide as the longest word
+        assert!(board.starts_with(&format!("{:<3}  ", game.board[0][0].word)));
+
+        let key = render_board(&game, true);
+        assert!(key.contains("X "));
+
+        // Duet cards show both sides of the key
+        let game = Game::new(&GameConfig::duet(), WordLists::builtin().default_list(), 0).unwrap();
+        let key = render_board(&game, true);
+        assert!(key.contains("AA ") && key.contains("XX ") && key.contains("A- "));
+    }
+
+    #[test]
+    fn manages_rooms_in_database() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        database.create_room("bar", &game).unwrap();
+        let (session_id, _) = database.create_session("foo").unwrap();
+
+        let mut store = DatabaseRooms(database.clone());
+        let names: Vec<String> = store.list().unwrap().into_iter().map(|room| room.name).collect();
+        assert_eq!(vec!["bar", "foo"], names);
+
+        let (room, players) = store.inspect("foo").unwrap();
+        assert_eq!(1, room.player_count);
+        assert_eq!(session_id, players[0].id);
+
+        store.delete("foo").unwrap();
+        assert!(database.get_room("foo").is_err());
+        assert!(database.get_session(&session_id).is_err());
+        assert!(store.delete("foo").is_err());
+    }
+}
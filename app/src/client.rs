@@ -1,16 +1,17 @@
 use std::{time::{Duration, Instant}};
 
 use actix::{
-    fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler,
-    Running, StreamHandler, WrapFuture,
+    dev::ToEnvelope, fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler,
+    Recipient, Running, StreamHandler, WrapFuture,
 };
 use actix_web_actors::ws::{self, WebsocketContext};
-use log::{warn};
+use log::{error, warn};
 use serde::{Serialize, Deserialize};
 
 use crate::{
     database::Database,
-    event::{ClientRequest, ClientRequestType, EventMessage, NewClientConnection},
+    event::{ClientControl, ClientDisconnected, ClientRequest, ClientRequestType, ErrorCode, Event, EventMessage, NewClientConnection},
+    game::Team,
     server::WsServer,
 };
 
@@ -20,50 +21,144 @@ pub struct ClientSession {
     pub username: String,
     pub room: String,
     pub is_spymaster: bool,
+    pub team: Option<Team>,
 }
 
 impl ClientSession {
-    pub fn new(id: usize, room: &String) -> ClientSession {
+    pub fn new(id: usize, room: &str) -> ClientSession {
         ClientSession {
             id,
             username: String::from(""),
-            room: room.clone(),
-            is_spymaster: false
+            room: room.to_string(),
+            is_spymaster: false,
+            team: None
         }
     }
 }
 
-#[allow(dead_code)]
+/// The players of a room, split up by the team they are on.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct TeamRosters {
+    pub red: Vec<ClientSession>,
+    pub blue: Vec<ClientSession>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub green: Vec<ClientSession>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub yellow: Vec<ClientSession>,
+    pub unassigned: Vec<ClientSession>,
+}
+
+impl TeamRosters {
+    pub fn from_sessions<I: IntoIterator<Item = ClientSession>>(sessions: I) -> TeamRosters {
+        let mut rosters = TeamRosters::default();
+        for session in sessions {
+            match session.team {
+                Some(Team::RED) => rosters.red.push(session),
+                Some(Team::BLUE) => rosters.blue.push(session),
+                Some(Team::GREEN) => rosters.green.push(session),
+                Some(Team::YELLOW) => rosters.yellow.push(session),
+                None => rosters.unassigned.push(session),
+            }
+        }
+        rosters
+    }
+
+    pub fn roster(&self, team: &Team) -> &Vec<ClientSession> {
+        match team {
+            Team::RED => &self.red,
+            Team::BLUE => &self.blue,
+            Team::GREEN => &self.green,
+            Team::YELLOW => &self.yellow,
+        }
+    }
+
+    /// The team out of `teams` the given player should be on to keep the teams
+    /// balanced, not counting the player themselves. Ties go to red, and then to
+    /// the first of the smallest teams.
+    pub fn balanced_team_for(&self, session_id: usize, teams: &[Team]) -> Team {
+        let team_size = |team: &Team| self.roster(team).iter().filter(|s| s.id != session_id).count();
+        teams.iter()
+            .min_by_key(|team| (team_size(team), **team != Team::RED))
+            .cloned()
+            .unwrap_or(Team::RED)
+    }
+}
+
+/// Where the server sends a player's events, which is their websocket connection
+/// or, for bots, the bot's actor.
+#[derive(Clone, PartialEq)]
+pub struct ClientAddr {
+    events: Recipient<EventMessage>,
+    control: Recipient<ClientControl>,
+}
+
+impl ClientAddr {
+    pub fn new<A>(addr: Addr<A>) -> ClientAddr
+    where
+        A: Actor + Handler<EventMessage> + Handler<ClientControl>,
+        A::Context: ToEnvelope<A, EventMessage> + ToEnvelope<A, ClientControl>,
+    {
+        ClientAddr { events: addr.clone().recipient(), control: addr.recipient() }
+    }
+
+    pub fn send(&self, event: EventMessage) {
+        self.events.do_send(event);
+    }
+
+    pub fn control(&self, control: ClientControl) {
+        self.control.do_send(control);
+    }
+
+    pub fn connected(&self) -> bool {
+        self.events.connected()
+    }
+}
+
 pub struct WsClient<T: 'static + Database + std::marker::Unpin> {
     session_id: usize,
     room_name: String,
     database: T,
     server: Addr<WsServer<T>>,
+    /// Hash of the room password this connection was opened with, see `NewClientConnection`.
+    password_hash: Option<String>,
+    resume_token: Option<String>,
+    heartbeat: Heartbeat,
     pub hb: Instant,
 }
 
 impl<T: 'static + Database + std::marker::Unpin> WsClient<T> {
-    pub fn new(server: Addr<WsServer<T>>, room: &String, database: T) -> Self {
+    pub fn new(
+        server: Addr<WsServer<T>>,
+        room: &str,
+        database: T,
+        password_hash: Option<String>,
+        resume_token: Option<String>,
+        heartbeat: Heartbeat,
+    ) -> Self {
         WsClient {
             session_id: 0,
             server,
             database,
-            room_name: room.clone(),
+            room_name: room.to_string(),
+            password_hash,
+            resume_token,
+            heartbeat,
             hb: Instant::now(),
         }
     }
 
     fn hb(&self, ctx: &mut WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.heartbeat.timeout {
                 // heartbeat timed out
                 warn!("Session id {} timed out. Disconnecting.", act.session_id);
 
                 // notify chat server
-                act.server.do_send(ClientRequest {
-                    sender_id: act.session_id,
-                    room_name: act.room_name.clone(),
-                    request: ClientRequestType::TimedOut { id: act.session_id },
+                act.server.do_send(ClientDisconnected {
+                    session_id: act.session_id,
+                    room: act.room_name.clone(),
+                    addr: ClientAddr::new(ctx.address()),
+                    timed_out: true,
                 });
 
                 // stop actor
@@ -78,11 +173,23 @@ impl<T: 'static + Database + std::marker::Unpin> WsClient<T> {
     }
 }
 
-/// How often heartbeat pings are sent
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often clients are pinged, and how long they have to answer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heartbeat {
+    /// How often heartbeat pings are sent
+    pub interval: Duration,
+    /// How long before lack of client response causes a timeout
+    pub timeout: Duration,
+}
 
-/// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
 
 /// Make actor from `ChatSession`
 impl<T: 'static + Database + std::marker::Unpin> Actor for WsClient<T> {
@@ -95,35 +202,80 @@ impl<T: 'static + Database + std::marker::Unpin> Actor for WsClient<T> {
         self.server
             .send(NewClientConnection {
                 room: self.room_name.clone(),
-                addr: ctx.address(),
+                addr: ClientAddr::new(ctx.address()),
+                password_hash: self.password_hash.take(),
+                resume_token: self.resume_token.take(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(id) => act.session_id = id,
-                    _ => ctx.stop(),
+                    Ok(Ok(id)) => act.session_id = id,
+                    Ok(Err(e)) => {
+                        warn!("Could not join room {}: {}", act.room_name, e);
+                        // Tell the player why before hanging up, they never got a session
+                        <Self as Handler<EventMessage>>::handle(act, EventMessage {
+                            sender: ClientSession::new(0, &act.room_name),
+                            room: act.room_name.clone(),
+                            event: Event::from(&e),
+                        }, ctx);
+                        ctx.close(Some(ws::CloseReason {
+                            code: ws::CloseCode::Policy,
+                            description: Some(e.to_string()),
+                        }));
+                        ctx.stop()
+                    }
+                    Err(e) => {
+                        error!("Could not reach the server: {}", e);
+                        ctx.stop()
+                    }
                 };
                 fut::ready(())
             })
             .wait(ctx);
     }
 
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
         // notify chat server
-        self.server.do_send(ClientRequest {
-            sender_id: self.session_id,
-            room_name: self.room_name.clone(),
-            request: ClientRequestType::Disconnect { id: self.session_id },
+        self.server.do_send(ClientDisconnected {
+            session_id: self.session_id,
+            room: self.room_name.clone(),
+            addr: ClientAddr::new(ctx.address()),
+            timed_out: false,
         });
         Running::Stop
     }
 }
 
+impl<T: 'static + Database + std::marker::Unpin> Handler<ClientControl> for WsClient<T> {
+    type Result = ();
+
+    fn handle(&mut self, control: ClientControl, ctx: &mut Self::Context) -> Self::Result {
+        match control {
+            ClientControl::Reassign { session_id } => self.session_id = session_id,
+            ClientControl::Replaced | ClientControl::Kicked | ClientControl::RoomClosed => {
+                let description = match control {
+                    ClientControl::Kicked => "The host removed you from the room.",
+                    ClientControl::RoomClosed => "The room was closed.",
+                    _ => "The session was resumed from another connection.",
+                };
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some(String::from(description)),
+                }));
+                ctx.stop();
+            }
+        }
+    }
+}
+
 impl<T: 'static + Database + std::marker::Unpin> Handler<EventMessage> for WsClient<T> {
     type Result = ();
 
     fn handle(&mut self, event_message: EventMessage, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(serde_json::to_string(&event_message).unwrap())
+        match serde_json::to_string(&event_message) {
+            Ok(text) => ctx.text(text),
+            Err(e) => error!("Could not serialize event for session {}: {}", self.session_id, e),
+        }
     }
 }
 
@@ -137,18 +289,71 @@ impl<T: 'static + Database + std::marker::Unpin> StreamHandler<Result<ws::Messag
             Ok(ws::Message::Pong(_)) => {
                 self.hb = Instant::now();
             }
-            Ok(ws::Message::Text(text)) => {
-                let client_request_type: ClientRequestType = serde_json::from_str(&text).unwrap();
-                self.server.do_send(ClientRequest {
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<ClientRequestType>(&text) {
+                Ok(client_request_type) => self.server.do_send(ClientRequest {
                     sender_id: self.session_id,
                     room_name: self.room_name.clone(),
                     request: client_request_type,
-                });
-            }
+                }),
+                Err(e) => {
+                    warn!("Could not parse request from session {}: {}", self.session_id, e);
+                    let sender = self.database
+                        .get_session(&self.session_id)
+                        .unwrap_or_else(|_| ClientSession::new(self.session_id, &self.room_name));
+                    <Self as Handler<EventMessage>>::handle(self, EventMessage {
+                        sender,
+                        room: self.room_name.clone(),
+                        event: Event::Error {
+                            code: ErrorCode::InvalidRequest,
+                            message: format!("Could not understand that request: {}", e),
+                        },
+                    }, ctx);
+                }
+            },
             Ok(ws::Message::Close(_)) => {
                 ctx.stop();
             }
-            _ => (warn!("Did not recognize event {:?}", msg)),
+            _ => warn!("Did not recognize event {:?}", msg),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::game::Team;
+
+    use super::{ClientSession, TeamRosters};
+
+    fn session_on_team(id: usize, team: Option<Team>) -> ClientSession {
+        ClientSession { team, ..ClientSession::new(id, "foo") }
+    }
+
+    #[test]
+    fn balances_teams() {
+        let mut rosters = TeamRosters::from_sessions(vec![
+            session_on_team(1, Some(Team::RED)),
+            session_on_team(2, None),
+        ]);
+        assert_eq!(1, rosters.red.len());
+        assert_eq!(0, rosters.blue.len());
+        assert_eq!(1, rosters.unassigned.len());
+        let two_teams = [Team::BLUE, Team::RED];
+        assert_eq!(Team::BLUE, rosters.balanced_team_for(2, &two_teams));
+        // The red player doesn't count against their own team
+        assert_eq!(Team::RED, rosters.balanced_team_for(1, &two_teams));
+
+        rosters.blue.push(session_on_team(3, Some(Team::BLUE)));
+        assert_eq!(Team::RED, rosters.balanced_team_for(2, &two_teams));
+
+        rosters.blue.push(session_on_team(4, Some(Team::BLUE)));
+        assert_eq!(Team::RED, rosters.balanced_team_for(2, &two_teams));
+        assert_eq!(Team::RED, rosters.balanced_team_for(1, &two_teams));
+
+        // A third team fills up once it's the smallest
+        let three_teams = [Team::BLUE, Team::RED, Team::GREEN];
+        assert_eq!(Team::GREEN, rosters.balanced_team_for(2, &three_teams));
+        rosters.green.push(session_on_team(5, Some(Team::GREEN)));
+        assert_eq!(Team::RED, rosters.balanced_team_for(2, &three_teams));
+        assert_eq!(vec![5], rosters.roster(&Team::GREEN).iter().map(|s| s.id).collect::<Vec<usize>>());
+    }
+}
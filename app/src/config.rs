@@ -0,0 +1,241 @@
+use std::{fmt, fs, path::{Path, PathBuf}, str::FromStr, time::Duration};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::client::Heartbeat;
+
+/// Where rooms, sessions and games are kept.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(try_from = "String")]
+pub enum DatabaseSetting {
+    /// Everything is lost when the server restarts.
+    #[default]
+    Memory,
+    Sqlite(PathBuf),
+}
+
+impl FromStr for DatabaseSetting {
+    type Err = anyhow::Error;
+
+    fn from_str(setting: &str) -> Result<Self> {
+        match setting.split_once(':') {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(DatabaseSetting::Sqlite(PathBuf::from(path))),
+            _ if setting == "memory" => Ok(DatabaseSetting::Memory),
+            _ => bail!("Unknown database '{}', expected 'memory' or 'sqlite:<path>'.", setting),
+        }
+    }
+}
+
+impl TryFrom<String> for DatabaseSetting {
+    type Error = anyhow::Error;
+
+    fn try_from(setting: String) -> Result<Self> {
+        setting.parse()
+    }
+}
+
+impl fmt::Display for DatabaseSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseSetting::Memory => write!(f, "memory"),
+            DatabaseSetting::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+        }
+    }
+}
+
+/// Server settings. Each setting comes from the first of these that has it: a
+/// command line flag, an environment variable, the TOML config file, or the default.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: String,
+    pub port: u16,
+    pub workers: usize,
+    /// How often clients are pinged, in seconds.
+    pub heartbeat_interval: u64,
+    /// How long a client can go without answering a ping before it is
+    /// disconnected, in seconds.
+    pub client_timeout: u64,
+    pub database: DatabaseSetting,
+    /// Directory of extra `.txt` word lists.
+    pub word_lists_dir: Option<PathBuf>,
+    /// An `env_logger` filter, like `info` or `rust_codenames=debug`.
+    pub log_level: String,
+    /// Secret for the admin API, which is turned off without one.
+    pub admin_token: Option<String>,
+    /// Word vectors in the GloVe text format for bots to play with. Rooms can't
+    /// have bots without them.
+    pub embeddings: Option<PathBuf>,
+    /// How many words to read from the start of the embeddings file.
+    pub embedding_words: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: String::from("0.0.0.0"),
+            port: 8080,
+            workers: 4,
+            heartbeat_interval: 5,
+            client_timeout: 10,
+            database: DatabaseSetting::Memory,
+            word_lists_dir: None,
+            log_level: String::from("info"),
+            admin_token: None,
+            embeddings: None,
+            embedding_words: 100_000,
+        }
+    }
+}
+
+impl Config {
+    /// Reads a TOML config file. Settings missing from the file keep their defaults.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .context(format!("Could not read config file '{}'.", path.display()))?;
+        toml::from_str(&text).context(format!("Could not parse config file '{}'.", path.display()))
+    }
+
+    /// Builds the config from the config file named by `args`, with any settings
+    /// given as flags or environment variables on top.
+    pub fn load(args: ConfigArgs) -> Result<Config> {
+        let config = match &args.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        let config = args.apply(config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(self.workers > 0, "The server needs at least one worker.");
+        ensure!(self.embedding_words > 0, "Bots need at least one word from the embeddings file.");
+        ensure!(self.heartbeat_interval > 0, "The heartbeat interval has to be at least a second.");
+        ensure!(
+            self.client_timeout > self.heartbeat_interval,
+            "The client timeout ({}s) has to be longer than the heartbeat interval ({}s).",
+            self.client_timeout, self.heartbeat_interval
+        );
+        Ok(())
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            interval: Duration::from_secs(self.heartbeat_interval),
+            timeout: Duration::from_secs(self.client_timeout),
+        }
+    }
+}
+
+/// Command line flags and environment variables that override the config file.
+#[derive(Parser, Debug, Clone, Default, PartialEq)]
+pub struct ConfigArgs {
+    /// TOML file to read settings from.
+    #[arg(long, env = "CODENAMES_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, defaults to every interface.
+    #[arg(long, env = "CODENAMES_BIND_ADDRESS")]
+    pub bind_address: Option<String>,
+    /// Port to listen on, defaults to 8080.
+    #[arg(long, env = "CODENAMES_PORT")]
+    pub port: Option<u16>,
+    /// Number of HTTP worker threads, defaults to 4.
+    #[arg(long, env = "CODENAMES_WORKERS")]
+    pub workers: Option<usize>,
+    /// Seconds between heartbeat pings.
+    #[arg(long, env = "CODENAMES_HEARTBEAT_INTERVAL")]
+    pub heartbeat_interval: Option<u64>,
+    /// Seconds without a pong before a client is disconnected.
+    #[arg(long, env = "CODENAMES_CLIENT_TIMEOUT")]
+    pub client_timeout: Option<u64>,
+    /// `memory` or `sqlite:<path to database file>`.
+    #[arg(long, env = "CODENAMES_DATABASE")]
+    pub database: Option<DatabaseSetting>,
+    /// Directory of extra `.txt` word lists.
+    #[arg(long, env = "CODENAMES_WORD_LISTS_DIR")]
+    pub word_lists_dir: Option<PathBuf>,
+    /// Log filter like `info` or `rust_codenames=debug`, defaults to `info`.
+    #[arg(long, env = "CODENAMES_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Secret the admin API expects as a bearer token, the admin API is off without one.
+    #[arg(long, env = "CODENAMES_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+    /// Word vectors file in the GloVe text format, which lets rooms add bots.
+    #[arg(long, env = "CODENAMES_EMBEDDINGS")]
+    pub embeddings: Option<PathBuf>,
+    /// How many words to read from the embeddings file, defaults to 100000.
+    #[arg(long, env = "CODENAMES_EMBEDDING_WORDS")]
+    pub embedding_words: Option<usize>,
+}
+
+impl ConfigArgs {
+    fn apply(self, config: Config) -> Config {
+        Config {
+            bind_address: self.bind_address.unwrap_or(config.bind_address),
+            port: self.port.unwrap_or(config.port),
+            workers: self.workers.unwrap_or(config.workers),
+            heartbeat_interval: self.heartbeat_interval.unwrap_or(config.heartbeat_interval),
+            client_timeout: self.client_timeout.unwrap_or(config.client_timeout),
+            database: self.database.unwrap_or(config.database),
+            word_lists_dir: self.word_lists_dir.or(config.word_lists_dir),
+            log_level: self.log_level.unwrap_or(config.log_level),
+            admin_token: self.admin_token.or(config.admin_token).filter(|token| !token.is_empty()),
+            embeddings: self.embeddings.or(config.embeddings),
+            embedding_words: self.embedding_words.unwrap_or(config.embedding_words),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::{Config, ConfigArgs, DatabaseSetting};
+
+    #[test]
+    fn parses_database_settings() {
+        assert_eq!(DatabaseSetting::Memory, "memory".parse().unwrap());
+        assert_eq!(DatabaseSetting::Sqlite(PathBuf::from("games.db")), "sqlite:games.db".parse().unwrap());
+        assert!("sqlite:".parse::<DatabaseSetting>().is_err());
+        assert!("postgres:games".parse::<DatabaseSetting>().is_err());
+    }
+
+    #[test]
+    fn loads_config_file_with_overrides() {
+        let path = std::env::temp_dir().join(format!("codenames-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "port = 9000\nworkers = 2\ndatabase = \"sqlite:games.db\"\n").unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(Config {
+            port: 9000,
+            workers: 2,
+            database: DatabaseSetting::Sqlite(PathBuf::from("games.db")),
+            ..Config::default()
+        }, config);
+
+        // Flags win over the file
+        let args = ConfigArgs::try_parse_from([
+            "codenames", "--config", path.to_str().unwrap(), "--workers", "8", "--log-level", "debug",
+        ]).unwrap();
+        let config = Config::load(args).unwrap();
+        assert_eq!((9000, 8, "debug"), (config.port, config.workers, config.log_level.as_str()));
+
+        std::fs::write(&path, "prot = 9000\n").unwrap();
+        assert!(Config::from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let args = ConfigArgs { heartbeat_interval: Some(10), client_timeout: Some(5), ..ConfigArgs::default() };
+        assert!(Config::load(args).is_err());
+        assert!(Config::load(ConfigArgs { workers: Some(0), ..ConfigArgs::default() }).is_err());
+        assert!(Config::load(ConfigArgs::default()).is_ok());
+    }
+}
@@ -1,31 +1,74 @@
-use std::{collections::{HashMap}, sync::{Mutex, Arc, MutexGuard}};
+use std::{collections::{HashMap}, sync::{Mutex, Arc, MutexGuard, PoisonError}};
 
-use rand::{Rng};
+use rand::{distributions::Alphanumeric, Rng};
 use anyhow::{Result, bail, Context, anyhow};
 
-use crate::{game::{Game}, client::ClientSession, server::Room};
+use crate::{game::{Game}, client::ClientSession, history::Move, server::Room};
+
+mod sqlite;
+
+pub use sqlite::SqliteDatabase;
 
 pub trait Database {
-    fn create_room(&mut self, name: &String) -> Result<String>;
-    fn remove_room(&mut self, name: &String) -> Result<()>;
-    fn get_room(&self, name: &String) -> Result<Room>;
+    fn create_room(&mut self, name: &str, game: &Game) -> Result<String>;
+    /// Removes the room along with its words and every game played in it.
+    fn remove_room(&mut self, name: &str) -> Result<()>;
+    fn get_room(&self, name: &str) -> Result<Room>;
     fn get_rooms(&self) -> Result<Vec<Room>>;
+    /// Makes the room private, or public again when `password_hash` is `None`.
+    fn set_room_password(&mut self, room: &str, password_hash: Option<&str>) -> Result<()>;
+    fn set_room_host(&mut self, room: &str, host: Option<usize>) -> Result<()>;
+    fn set_room_locked(&mut self, room: &str, locked: bool) -> Result<()>;
     fn get_sessions(&self) -> Result<Vec<ClientSession>>;
     fn get_session(&self, id: &usize) -> Result<ClientSession>;
     fn update_session(&mut self, id: usize, session_update: &ClientSession) -> Result<()>;
-    fn create_session(&mut self, room: &String) -> Result<usize>;
+    /// Creates an empty session in the room and returns its id along with the secret
+    /// token the player can use to resume the session later.
+    fn create_session(&mut self, room: &str) -> Result<(usize, String)>;
+    fn get_session_by_resume_token(&self, token: &str) -> Result<ClientSession>;
     fn remove_session(&mut self, session_id: usize) -> Result<()>;
+    /// Deals a new game in the room. The room's earlier games are kept so they can
+    /// still be exported.
+    fn create_game(&mut self, room: &str, game: &Game) -> Result<usize>;
     fn update_game(&mut self, game_id: usize, game_update: &Game) -> Result<()>;
     fn flip_card(&mut self, game_id: usize, coord: (usize, usize)) -> Result<Game>;
     fn get_game(&self, game_id: usize) -> Result<Game>;
     fn next_turn(&mut self, game_id: usize) -> Result<Game>;
+    fn give_clue(&mut self, game_id: usize, word: &str, count: u8) -> Result<Game>;
+    fn set_room_words(&mut self, room: &str, words: &[String]) -> Result<()>;
+    fn get_room_words(&self, room: &str) -> Result<Option<Vec<String>>>;
+    fn log_move(&mut self, game_id: usize, game_move: &Move) -> Result<()>;
+    /// Every move made in the game, oldest first.
+    fn get_moves(&self, game_id: usize) -> Result<Vec<Move>>;
+}
+
+/// A random room, game or session id. Ids are sent to browsers as JSON numbers, so
+/// they are kept within the integers JavaScript can represent exactly.
+fn random_id() -> usize {
+    rand::thread_rng().gen_range(0..(1 << 53))
 }
 
-#[derive(Clone)]
+/// A random token that is only ever sent to the player who owns the session.
+fn new_resume_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RESUME_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+const RESUME_TOKEN_LENGTH: usize = 32;
+
+#[derive(Clone, Default)]
 pub struct MemoryDatabaseTables {
     rooms: HashMap<String, Room>,
     games: HashMap<usize, Game>,
     sessions: HashMap<usize, ClientSession>,
+    room_words: HashMap<String, Vec<String>>,
+    resume_tokens: HashMap<String, usize>,
+    moves: HashMap<usize, Vec<Move>>,
+    /// Every game dealt in each room, so they go when the room does.
+    room_games: HashMap<String, Vec<usize>>,
 }
 
 impl MemoryDatabaseTables {
@@ -34,11 +77,15 @@ impl MemoryDatabaseTables {
             rooms: HashMap::new(),
             games: HashMap::new(),
             sessions: HashMap::new(),
+            room_words: HashMap::new(),
+            resume_tokens: HashMap::new(),
+            moves: HashMap::new(),
+            room_games: HashMap::new(),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MemoryDatabase {
     database: Arc<Mutex<MemoryDatabaseTables>>
 }
@@ -50,40 +97,59 @@ impl MemoryDatabase {
         }
     }
 
-    fn get_lock(&self) -> MutexGuard<MemoryDatabaseTables> {
-        self.database.lock().unwrap()
+    /// A request that panicked while holding the lock shouldn't lock every other
+    /// room out of the database, so poisoning is ignored.
+    fn get_lock(&self) -> MutexGuard<'_, MemoryDatabaseTables> {
+        self.database.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    fn get_lock_mut(&mut self) -> MutexGuard<MemoryDatabaseTables> {
-        self.database.lock().unwrap()
+    fn get_lock_mut(&mut self) -> MutexGuard<'_, MemoryDatabaseTables> {
+        self.database.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Applies `update` to a game.
+    fn modify_game<F: FnOnce(&Game) -> Game>(&mut self, game_id: usize, update: F) -> Result<Game> {
+        let mut locked_database = self.get_lock_mut();
+        let game = locked_database.games
+            .get_mut(&game_id)
+            .context(format!("Could not find game with id '{}'.", game_id))?;
+        *game = update(game);
+        Ok(game.clone())
     }
 }
 
 impl Database for MemoryDatabase {
-    fn create_room(&mut self, name: &String) -> Result<String> {
+    fn create_room(&mut self, name: &str, game: &Game) -> Result<String> {
         if self.get_lock().rooms.contains_key(name) {
             bail!("Room {} already exists!", name)
         }
 
         loop {
-            let game_id = rand::thread_rng().gen();
+            let game_id = random_id();
             if self.get_lock().games.contains_key(&game_id) { continue; }
 
-            self.get_lock().games.insert(game_id, Game::new());
-            let new_room = Room::new(name.clone(), game_id);
-            self.get_lock().rooms.insert(name.clone(), new_room.clone());
-            return Ok(name.clone())
+            self.get_lock().games.insert(game_id, game.clone());
+            self.get_lock().room_games.insert(name.to_string(), vec![game_id]);
+            let new_room = Room::new(name.to_string(), game_id);
+            self.get_lock().rooms.insert(name.to_string(), new_room.clone());
+            return Ok(name.to_string())
         }
     }
 
-    fn remove_room(&mut self, name: &String) -> Result<()> {
-        self.get_lock().rooms
+    fn remove_room(&mut self, name: &str) -> Result<()> {
+        let mut tables = self.get_lock_mut();
+        tables.rooms
             .remove(name)
-            .context(format!("Failed to remove room with name '{}' because it did not exist.", name))
-            .and(Ok(()))
+            .context(format!("Failed to remove room with name '{}' because it did not exist.", name))?;
+        tables.room_words.remove(name);
+        for game_id in tables.room_games.remove(name).unwrap_or_default() {
+            tables.games.remove(&game_id);
+            tables.moves.remove(&game_id);
+        }
+        Ok(())
     }
 
-    fn get_room(&self, name: &String) -> Result<Room> {
+    fn get_room(&self, name: &str) -> Result<Room> {
         self.get_lock().rooms
             .get(name)
             .context(format!("Could not find room with name '{}'.", name))
@@ -94,6 +160,30 @@ impl Database for MemoryDatabase {
         Ok(self.get_lock().rooms.values().cloned().collect())
     }
 
+    fn set_room_password(&mut self, room: &str, password_hash: Option<&str>) -> Result<()> {
+        self.get_lock().rooms
+            .get_mut(room)
+            .context(format!("Could not find room with name '{}'.", room))?
+            .password_hash = password_hash.map(String::from);
+        Ok(())
+    }
+
+    fn set_room_host(&mut self, room: &str, host: Option<usize>) -> Result<()> {
+        self.get_lock().rooms
+            .get_mut(room)
+            .context(format!("Could not find room with name '{}'.", room))?
+            .host = host;
+        Ok(())
+    }
+
+    fn set_room_locked(&mut self, room: &str, locked: bool) -> Result<()> {
+        self.get_lock().rooms
+            .get_mut(room)
+            .context(format!("Could not find room with name '{}'.", room))?
+            .locked = locked;
+        Ok(())
+    }
+
     fn get_sessions(&self) -> Result<Vec<ClientSession>> {
         Ok(self.get_lock().sessions.values().cloned().collect())
     }
@@ -101,50 +191,73 @@ impl Database for MemoryDatabase {
     fn get_session(&self, id: &usize) -> Result<ClientSession> {
         self.get_lock()
             .sessions
-            .get(&id)
+            .get(id)
             .context(format!("Session with id {} does not exist.", id))
             .cloned()
     }
 
-    fn create_session(&mut self, room: &String) -> Result<usize> {
+    fn create_session(&mut self, room: &str) -> Result<(usize, String)> {
         loop {
-            let id = rand::thread_rng().gen();
+            let id = random_id();
             if self.get_lock().sessions.contains_key(&id) { continue; };
 
-            let session = ClientSession::new(id, &room);
+            let session = ClientSession::new(id, room);
             self.get_lock().sessions.insert(id, session);
 
-            return match self.get_lock().rooms.get_mut(room) {
+            let mut tables = self.get_lock();
+            return match tables.rooms.get_mut(room) {
                 Some(val) => {
                     val.sessions.push(id);
-                    Ok(id)
+                    let token = new_resume_token();
+                    tables.resume_tokens.insert(token.clone(), id);
+                    Ok((id, token))
                 },
                 None => Err(anyhow!("Could not find room with name '{}'.", room))
             }
         } 
     }
 
+    fn get_session_by_resume_token(&self, token: &str) -> Result<ClientSession> {
+        let id = *self.get_lock()
+            .resume_tokens
+            .get(token)
+            .context("No session has that resume token.")?;
+        self.get_session(&id)
+    }
+
     fn remove_session(&mut self, session_id: usize) -> Result<()> {
         let session = self.get_session(&session_id)?.clone();
         self.get_lock().sessions.remove(&session_id);
+        self.get_lock().resume_tokens.retain(|_, id| *id != session_id);
 
         self.get_lock_mut()
             .rooms
             .get_mut(&session.room)
             .context(format!("Could not find room with name '{}'.", &session.room))
-            .and_then(|room| {
-                if let Some(pos) = room.sessions.iter().position(|s| *s == session_id) {
-                    room.sessions.swap_remove(pos);
-                }
-                Ok(())
-            })
+            .map(|room| room.sessions.retain(|s| *s != session_id))
+    }
+
+    fn create_game(&mut self, room: &str, game: &Game) -> Result<usize> {
+        let mut tables = self.get_lock_mut();
+        if !tables.rooms.contains_key(room) {
+            bail!("Could not find room with name '{}'.", room)
+        }
+        loop {
+            let game_id = random_id();
+            if tables.games.contains_key(&game_id) { continue; }
+
+            tables.games.insert(game_id, game.clone());
+            tables.room_games.entry(room.to_string()).or_default().push(game_id);
+            if let Some(room) = tables.rooms.get_mut(room) {
+                room.game_id = game_id;
+            }
+            return Ok(game_id)
+        }
     }
 
     fn update_game(&mut self, game_id: usize, game_update: &Game) -> Result<()> {
-        self.get_lock().games.get(&game_id)
+        self.modify_game(game_id, |_| game_update.clone())
             .context(format!("Cannot find game with id '{}'.", game_id))?;
-        
-        self.get_lock().games.insert(game_id, game_update.clone());
         Ok(())
     }
 
@@ -165,37 +278,83 @@ impl Database for MemoryDatabase {
     }
 
     fn flip_card(&mut self, game_id: usize, coord: (usize, usize)) -> Result<Game> {
-        let mut locked_database = self.get_lock();
-        let game = locked_database.games
-            .get_mut(&game_id)
-            .context(format!("Could not find game with id '{}'.", game_id))?;
-        let updated_game = game.flip_card(coord);
-        *game = updated_game.clone();
-        Ok(updated_game.clone())
+        self.modify_game(game_id, |game| game.flip_card(coord))
     }
 
     fn next_turn(&mut self, game_id: usize) -> Result<Game> {
-        let mut locked_database = self.get_lock();
-        let game = locked_database.games
-            .get_mut(&game_id)
-            .context(format!("Could not find game with id '{}'.", game_id))?;
-        let updated_game = game.next_turn();
-        *game = updated_game.clone();
-        Ok(updated_game)
+        self.modify_game(game_id, |game| game.next_turn())
+    }
+
+    fn give_clue(&mut self, game_id: usize, word: &str, count: u8) -> Result<Game> {
+        self.modify_game(game_id, |game| game.give_clue(word, count))
+    }
+
+    fn set_room_words(&mut self, room: &str, words: &[String]) -> Result<()> {
+        self.get_room(room)?;
+        self.get_lock().room_words.insert(room.to_string(), words.to_vec());
+        Ok(())
+    }
+
+    fn get_room_words(&self, room: &str) -> Result<Option<Vec<String>>> {
+        self.get_room(room)?;
+        Ok(self.get_lock().room_words.get(room).cloned())
+    }
+
+    fn log_move(&mut self, game_id: usize, game_move: &Move) -> Result<()> {
+        self.get_game(game_id)?;
+        self.get_lock_mut().moves.entry(game_id).or_default().push(game_move.clone());
+        Ok(())
+    }
+
+    fn get_moves(&self, game_id: usize) -> Result<Vec<Move>> {
+        self.get_game(game_id)?;
+        Ok(self.get_lock().moves.get(&game_id).cloned().unwrap_or_default())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::client::ClientSession;
+    use crate::{
+        client::ClientSession,
+        game::{Game, GameConfig, Team},
+        history::{Move, MoveAction},
+        words::WordLists,
+    };
+
+    use super::{Database, MemoryDatabase, SqliteDatabase};
+
+    /// Runs a test body against every `Database` backend.
+    macro_rules! backend_tests {
+        ($($name:ident),*) => {
+            mod memory {
+                $(
+                    #[test]
+                    fn $name() {
+                        super::$name(super::MemoryDatabase::new());
+                    }
+                )*
+            }
+
+            mod sqlite {
+                $(
+                    #[test]
+                    fn $name() {
+                        super::$name(super::SqliteDatabase::open_in_memory().unwrap());
+                    }
+                )*
+            }
+        };
+    }
+
+    backend_tests!(creates_gets_removes_room, creates_deletes_session, stores_room_words, stores_room_passwords, stores_room_moderation, keeps_session_order, logs_moves, removes_room_games);
 
-    use super::{Database, MemoryDatabase};
+    fn new_game() -> Game {
+        Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap()
+    }
 
-    #[test]
-    fn creates_gets_removes_room() {
-        let mut db = MemoryDatabase::new();
+    fn creates_gets_removes_room<T: Database>(mut db: T) {
         let room_name = String::from("foo");
-        db.create_room(&String::from("foo")).unwrap();
+        db.create_room(&String::from("foo"), &new_game()).unwrap();
         let room = db.get_room(&room_name).unwrap();
         
         assert_eq!(room_name, room.name);
@@ -204,26 +363,25 @@ mod tests {
         let game = db.get_game(room.game_id).unwrap();
         assert_eq!(5, game.board.len());
 
+        // updates game
+        let new_game = game.flip_card((0, 0));
+        db.update_game(room.game_id, &new_game).unwrap();
+        let new_game = db.get_game(room.game_id).unwrap();
+        assert!(new_game.board[0][0].flipped);
+
         db.remove_room(&room_name).unwrap();
         assert!(!db.get_rooms()
                     .unwrap()
-                    .contains(&&room));
+                    .contains(&room));
 
         let bad_get = db.get_room(&room_name);
         assert!(bad_get.is_err());
         let error_msg = bad_get.expect_err("Did not get an error").to_string();
         assert_eq!("Could not find room with name 'foo'.", error_msg);
-
-        // updates game
-        let new_game = game.flip_card((0, 0));
-        db.update_game(room.game_id, &new_game).unwrap();
-        let new_game = db.get_game(room.game_id).unwrap();
-        assert!(new_game.board[0][0].flipped);
+        assert!(db.get_game(room.game_id).is_err());
     }
 
-    #[test]
-    fn creates_deletes_session() {
-        let mut db = MemoryDatabase::new();
+    fn creates_deletes_session<T: Database>(mut db: T) {
         let room_name = String::from("foo");
         let username = String::from("foo_user");
 
@@ -234,10 +392,10 @@ mod tests {
         assert_eq!("Could not find room with name 'foo'.", error_msg);
 
         // Create room
-        db.create_room(&room_name).unwrap();
+        db.create_room(&room_name, &new_game()).unwrap();
 
         // Create session
-        let new_session_id = db.create_session(&room_name).unwrap();
+        let (new_session_id, resume_token) = db.create_session(&room_name).unwrap();
         let session_id_exists_in_sessions = db.get_sessions()
             .unwrap()
             .into_iter()
@@ -249,6 +407,14 @@ mod tests {
         // Add username
         let new_session = db.get_session(&new_session_id).unwrap();
         db.update_session(new_session_id, &ClientSession {username: username.clone(), ..new_session.clone()}).unwrap();
+        assert_eq!(None, db.get_session(&new_session_id).unwrap().team);
+
+        // Join a team
+        db.update_session(new_session_id, &ClientSession {
+            team: Some(Team::BLUE),
+            ..db.get_session(&new_session_id).unwrap()
+        }).unwrap();
+        assert_eq!(Some(Team::BLUE), db.get_session(&new_session_id).unwrap().team);
 
         let username_exists_in_sessions = db.get_sessions()
             .unwrap()
@@ -264,7 +430,13 @@ mod tests {
             .contains(&new_session_id);
         assert!(username_exists_in_room);
 
+        // The resume token finds the session again
+        assert_eq!(32, resume_token.len());
+        assert_eq!(username, db.get_session_by_resume_token(&resume_token).unwrap().username);
+        assert!(db.get_session_by_resume_token("not a token").is_err());
+
         db.remove_session(new_session_id).unwrap();
+        assert!(db.get_session_by_resume_token(&resume_token).is_err());
         let username_exists = db.get_sessions()
             .unwrap()
             .into_iter()
@@ -279,4 +451,112 @@ mod tests {
             .contains(&new_session_id);
         assert!(!username_exists_in_room);
     }
+
+    fn stores_room_words<T: Database>(mut db: T) {
+        let room_name = String::from("foo");
+        let words = vec![String::from("APPLE"), String::from("BANANA")];
+
+        assert!(db.set_room_words(&room_name, &words).is_err());
+
+        db.create_room(&room_name, &new_game()).unwrap();
+        assert_eq!(None, db.get_room_words(&room_name).unwrap());
+
+        db.set_room_words(&room_name, &words).unwrap();
+        assert_eq!(Some(words), db.get_room_words(&room_name).unwrap());
+
+        // Removing the room removes its words
+        db.remove_room(&room_name).unwrap();
+        db.create_room(&room_name, &new_game()).unwrap();
+        assert_eq!(None, db.get_room_words(&room_name).unwrap());
+    }
+
+    fn stores_room_passwords<T: Database>(mut db: T) {
+        let room_name = String::from("foo");
+        assert!(db.set_room_password(&room_name, Some("hash")).is_err());
+
+        db.create_room(&room_name, &new_game()).unwrap();
+        assert_eq!(None, db.get_room(&room_name).unwrap().password_hash);
+
+        db.set_room_password(&room_name, Some("hash")).unwrap();
+        assert_eq!(Some(String::from("hash")), db.get_room(&room_name).unwrap().password_hash);
+
+        db.set_room_password(&room_name, None).unwrap();
+        assert_eq!(None, db.get_room(&room_name).unwrap().password_hash);
+    }
+
+    fn stores_room_moderation<T: Database>(mut db: T) {
+        let room_name = String::from("foo");
+        assert!(db.set_room_host(&room_name, Some(1)).is_err());
+        assert!(db.set_room_locked(&room_name, true).is_err());
+
+        db.create_room(&room_name, &new_game()).unwrap();
+        let (session_id, _) = db.create_session(&room_name).unwrap();
+        let room = db.get_room(&room_name).unwrap();
+        assert_eq!((None, false), (room.host, room.locked));
+
+        db.set_room_host(&room_name, Some(session_id)).unwrap();
+        db.set_room_locked(&room_name, true).unwrap();
+        let room = db.get_room(&room_name).unwrap();
+        assert_eq!((Some(session_id), true), (room.host, room.locked));
+        assert_eq!(Some(session_id), room.current_host());
+
+        // The host is only current while they are in the room
+        db.remove_session(session_id).unwrap();
+        assert_eq!(None, db.get_room(&room_name).unwrap().current_host());
+    }
+
+    fn keeps_session_order<T: Database>(mut db: T) {
+        db.create_room("foo", &new_game()).unwrap();
+        let ids: Vec<usize> = (0..4).map(|_| db.create_session("foo").unwrap().0).collect();
+
+        // The host is handed to the first player left, so leaving mustn't reorder the rest
+        db.remove_session(ids[0]).unwrap();
+        assert_eq!(ids[1..].to_vec(), db.get_room("foo").unwrap().sessions);
+        db.remove_session(ids[2]).unwrap();
+        assert_eq!(vec![ids[1], ids[3]], db.get_room("foo").unwrap().sessions);
+    }
+
+    fn logs_moves<T: Database>(mut db: T) {
+        db.create_room("foo", &new_game()).unwrap();
+        let first_game_id = db.get_room("foo").unwrap().game_id;
+        db.log_move(first_game_id, &Move::new(1, MoveAction::NextTurn {})).unwrap();
+
+        // A new game gets its own id and log, and the old game is kept
+        let game_id = db.create_game("foo", &new_game().next_turn()).unwrap();
+        assert_ne!(first_game_id, game_id);
+        assert_eq!(game_id, db.get_room("foo").unwrap().game_id);
+        assert_eq!(new_game(), db.get_game(first_game_id).unwrap());
+        assert!(db.create_game("bar", &new_game()).is_err());
+
+        let moves = vec![
+            Move::new(1, MoveAction::GiveClue { word: String::from("foo"), count: 1 }),
+            Move::new(2, MoveAction::FlipCard { coord: (1, 2) }),
+        ];
+        for game_move in &moves {
+            db.log_move(game_id, game_move).unwrap();
+        }
+        assert_eq!(moves, db.get_moves(game_id).unwrap());
+        assert_eq!(1, db.get_moves(first_game_id).unwrap().len());
+        assert!(db.log_move(game_id + 1, &moves[0]).is_err());
+    }
+
+    fn removes_room_games<T: Database>(mut db: T) {
+        db.create_room("foo", &new_game()).unwrap();
+        db.create_room("bar", &new_game()).unwrap();
+        let first_game_id = db.get_room("foo").unwrap().game_id;
+        db.log_move(first_game_id, &Move::new(1, MoveAction::NextTurn {})).unwrap();
+        let game_id = db.create_game("foo", &new_game()).unwrap();
+        db.flip_card(game_id, (0, 0)).unwrap();
+        db.log_move(game_id, &Move::new(1, MoveAction::FlipCard { coord: (0, 0) })).unwrap();
+        let other_game_id = db.get_room("bar").unwrap().game_id;
+
+        // Every game the room played goes with it, and other rooms keep theirs
+        db.remove_room("foo").unwrap();
+        for id in [first_game_id, game_id] {
+            assert!(db.get_game(id).is_err());
+            assert!(db.get_moves(id).is_err());
+        }
+        assert!(db.get_game(other_game_id).is_ok());
+        assert!(db.remove_room("foo").is_err());
+    }
 }
@@ -0,0 +1,466 @@
+use std::{path::Path, sync::{Arc, Mutex, MutexGuard, PoisonError}};
+
+use anyhow::{Result, bail, Context};
+use rusqlite::{
+    Connection, OptionalExtension, params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef},
+};
+
+use crate::{game::{Game, Team}, client::ClientSession, history::Move, server::Room};
+
+use super::{new_resume_token, random_id, Database};
+
+/// Schema migrations, applied in order. The index of a migration + 1 is the
+/// `user_version` the database is at once that migration has run, so new
+/// migrations must only ever be appended to the end of this list.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE rooms (
+        name TEXT PRIMARY KEY NOT NULL,
+        game_id INTEGER NOT NULL
+    );
+    CREATE TABLE games (
+        id INTEGER PRIMARY KEY NOT NULL,
+        state TEXT NOT NULL
+    );
+    CREATE TABLE sessions (
+        id INTEGER UNIQUE NOT NULL,
+        room TEXT NOT NULL,
+        username TEXT NOT NULL,
+        is_spymaster INTEGER NOT NULL
+    );",
+    "ALTER TABLE sessions ADD COLUMN team TEXT;",
+    "CREATE TABLE room_words (
+        room TEXT PRIMARY KEY NOT NULL,
+        words TEXT NOT NULL
+    );",
+    "ALTER TABLE sessions ADD COLUMN resume_token TEXT;
+    CREATE UNIQUE INDEX sessions_resume_token ON sessions (resume_token);",
+    "CREATE TABLE game_history (
+        game_id INTEGER NOT NULL,
+        state TEXT NOT NULL
+    );
+    CREATE INDEX game_history_game_id ON game_history (game_id);",
+    "CREATE TABLE moves (
+        game_id INTEGER NOT NULL,
+        entry TEXT NOT NULL
+    );
+    CREATE INDEX moves_game_id ON moves (game_id);",
+    "ALTER TABLE rooms ADD COLUMN password_hash TEXT;",
+    "ALTER TABLE rooms ADD COLUMN host INTEGER;
+    ALTER TABLE rooms ADD COLUMN locked INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE games ADD COLUMN room TEXT;
+    UPDATE games SET room = (SELECT name FROM rooms WHERE rooms.game_id = games.id);
+    CREATE INDEX games_room ON games (room);",
+    "DROP TABLE game_history;",
+];
+
+impl ToSql for Team {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            Team::RED => "RED",
+            Team::BLUE => "BLUE",
+            Team::GREEN => "GREEN",
+            Team::YELLOW => "YELLOW",
+        }))
+    }
+}
+
+impl FromSql for Team {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "RED" => Ok(Team::RED),
+            "BLUE" => Ok(Team::BLUE),
+            "GREEN" => Ok(Team::GREEN),
+            "YELLOW" => Ok(Team::YELLOW),
+            other => Err(FromSqlError::Other(format!("Unknown team '{}'.", other).into())),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    connection: Arc<Mutex<Connection>>
+}
+
+impl SqliteDatabase {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteDatabase> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .context(format!("Could not open sqlite database at '{}'.", path.display()))?;
+        SqliteDatabase::from_connection(connection)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<SqliteDatabase> {
+        SqliteDatabase::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut connection: Connection) -> Result<SqliteDatabase> {
+        SqliteDatabase::migrate(&mut connection)?;
+        Ok(SqliteDatabase {
+            connection: Arc::new(Mutex::new(connection))
+        })
+    }
+
+    fn migrate(connection: &mut Connection) -> Result<()> {
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            bail!("Database schema version {} is newer than this server supports ({}).", version, MIGRATIONS.len())
+        }
+
+        let transaction = connection.transaction()?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            transaction.execute_batch(migration)
+                .context(format!("Failed to apply database migration {}.", i + 1))?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn get_lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_game(connection: &Connection, game_id: usize) -> Result<Game> {
+        let state: String = connection
+            .query_row("SELECT state FROM games WHERE id = ?1", [game_id as i64], |row| row.get(0))
+            .optional()?
+            .context(format!("Could not find game with id '{}'.", game_id))?;
+        serde_json::from_str(&state).context(format!("Could not parse game with id '{}'.", game_id))
+    }
+
+    fn insert_game(connection: &Connection, game_id: usize, room: &str, game: &Game) -> Result<()> {
+        connection.execute(
+            "INSERT INTO games (id, state, room) VALUES (?1, ?2, ?3)",
+            params![game_id as i64, serde_json::to_string(game)?, room],
+        )?;
+        Ok(())
+    }
+
+    fn write_game(connection: &Connection, game_id: usize, game: &Game) -> Result<()> {
+        connection.execute(
+            "UPDATE games SET state = ?2 WHERE id = ?1",
+            params![game_id as i64, serde_json::to_string(game)?],
+        )?;
+        Ok(())
+    }
+
+    fn read_room(connection: &Connection, name: &str) -> Result<Room> {
+        let (game_id, password_hash, host, locked): (i64, Option<String>, Option<i64>, bool) = connection
+            .query_row(
+                "SELECT game_id, password_hash, host, locked FROM rooms WHERE name = ?1",
+                [name],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            )
+            .optional()?
+            .context(format!("Could not find room with name '{}'.", name))?;
+
+        let mut statement = connection.prepare("SELECT id FROM sessions WHERE room = ?1 ORDER BY rowid")?;
+        let sessions = statement
+            .query_map([name], |row| row.get::<_, i64>(0))?
+            .map(|id| id.map(|id| id as usize))
+            .collect::<rusqlite::Result<Vec<usize>>>()?;
+
+        Ok(Room {
+            sessions,
+            password_hash,
+            host: host.map(|host| host as usize),
+            locked,
+            ..Room::new(name.to_string(), game_id as usize)
+        })
+    }
+
+    fn session_from_row(row: &rusqlite::Row) -> rusqlite::Result<ClientSession> {
+        Ok(ClientSession {
+            id: row.get::<_, i64>("id")? as usize,
+            username: row.get("username")?,
+            room: row.get("room")?,
+            is_spymaster: row.get("is_spymaster")?,
+            team: row.get("team")?,
+        })
+    }
+
+    /// Loads a game, applies `update` to it and stores the result in one step.
+    fn modify_game<F: FnOnce(&Game) -> Game>(&mut self, game_id: usize, update: F) -> Result<Game> {
+        let mut connection = self.get_lock();
+        let transaction = connection.transaction()?;
+        let updated_game = update(&SqliteDatabase::read_game(&transaction, game_id)?);
+        SqliteDatabase::write_game(&transaction, game_id, &updated_game)?;
+        transaction.commit()?;
+        Ok(updated_game)
+    }
+}
+
+impl Database for SqliteDatabase {
+    fn create_room(&mut self, name: &str, game: &Game) -> Result<String> {
+        let connection = self.get_lock();
+        let exists = connection
+            .query_row("SELECT 1 FROM rooms WHERE name = ?1", [name], |_| Ok(()))
+            .optional()?
+            .is_some();
+        if exists {
+            bail!("Room {} already exists!", name)
+        }
+
+        loop {
+            let game_id = random_id();
+            let taken = connection
+                .query_row("SELECT 1 FROM games WHERE id = ?1", [game_id as i64], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if taken { continue; }
+
+            SqliteDatabase::insert_game(&connection, game_id, name, game)?;
+            connection.execute(
+                "INSERT INTO rooms (name, game_id) VALUES (?1, ?2)",
+                params![name, game_id as i64],
+            )?;
+            return Ok(name.to_string())
+        }
+    }
+
+    fn remove_room(&mut self, name: &str) -> Result<()> {
+        let mut connection = self.get_lock();
+        let transaction = connection.transaction()?;
+        let removed = transaction.execute("DELETE FROM rooms WHERE name = ?1", [name])?;
+        if removed == 0 {
+            bail!("Failed to remove room with name '{}' because it did not exist.", name)
+        }
+        transaction.execute("DELETE FROM room_words WHERE room = ?1", [name])?;
+        transaction.execute("DELETE FROM moves WHERE game_id IN (SELECT id FROM games WHERE room = ?1)", [name])?;
+        transaction.execute("DELETE FROM games WHERE room = ?1", [name])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn get_room(&self, name: &str) -> Result<Room> {
+        SqliteDatabase::read_room(&self.get_lock(), name)
+    }
+
+    fn get_rooms(&self) -> Result<Vec<Room>> {
+        let connection = self.get_lock();
+        let mut statement = connection.prepare("SELECT name FROM rooms")?;
+        let names = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        names.iter()
+            .map(|name| SqliteDatabase::read_room(&connection, name))
+            .collect()
+    }
+
+    fn set_room_password(&mut self, room: &str, password_hash: Option<&str>) -> Result<()> {
+        let updated = self.get_lock()
+            .execute("UPDATE rooms SET password_hash = ?1 WHERE name = ?2", params![password_hash, room])?;
+        if updated == 0 {
+            bail!("Could not find room with name '{}'.", room)
+        }
+        Ok(())
+    }
+
+    fn set_room_host(&mut self, room: &str, host: Option<usize>) -> Result<()> {
+        let updated = self.get_lock()
+            .execute("UPDATE rooms SET host = ?1 WHERE name = ?2", params![host.map(|host| host as i64), room])?;
+        if updated == 0 {
+            bail!("Could not find room with name '{}'.", room)
+        }
+        Ok(())
+    }
+
+    fn set_room_locked(&mut self, room: &str, locked: bool) -> Result<()> {
+        let updated = self.get_lock()
+            .execute("UPDATE rooms SET locked = ?1 WHERE name = ?2", params![locked, room])?;
+        if updated == 0 {
+            bail!("Could not find room with name '{}'.", room)
+        }
+        Ok(())
+    }
+
+    fn get_sessions(&self) -> Result<Vec<ClientSession>> {
+        let connection = self.get_lock();
+        let mut statement = connection.prepare("SELECT * FROM sessions")?;
+        let sessions = statement
+            .query_map([], SqliteDatabase::session_from_row)?
+            .collect::<rusqlite::Result<Vec<ClientSession>>>()?;
+        Ok(sessions)
+    }
+
+    fn get_session(&self, id: &usize) -> Result<ClientSession> {
+        self.get_lock()
+            .query_row("SELECT * FROM sessions WHERE id = ?1", [*id as i64], SqliteDatabase::session_from_row)
+            .optional()?
+            .context(format!("Session with id {} does not exist.", id))
+    }
+
+    fn update_session(&mut self, id: usize, session_update: &ClientSession) -> Result<()> {
+        let updated = self.get_lock().execute(
+            "UPDATE sessions SET room = ?2, username = ?3, is_spymaster = ?4, team = ?5 WHERE id = ?1",
+            params![
+                id as i64,
+                session_update.room,
+                session_update.username,
+                session_update.is_spymaster,
+                session_update.team
+            ],
+        )?;
+        if updated == 0 {
+            bail!("Could not find session with id '{}'.", id)
+        }
+        Ok(())
+    }
+
+    fn create_session(&mut self, room: &str) -> Result<(usize, String)> {
+        let connection = self.get_lock();
+        SqliteDatabase::read_room(&connection, room)?;
+
+        loop {
+            let id = random_id();
+            let taken = connection
+                .query_row("SELECT 1 FROM sessions WHERE id = ?1", [id as i64], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if taken { continue; }
+
+            let session = ClientSession::new(id, room);
+            let token = new_resume_token();
+            connection.execute(
+                "INSERT INTO sessions (id, room, username, is_spymaster, team, resume_token) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![id as i64, session.room, session.username, session.is_spymaster, session.team, token],
+            )?;
+            return Ok((id, token))
+        }
+    }
+
+    fn get_session_by_resume_token(&self, token: &str) -> Result<ClientSession> {
+        self.get_lock()
+            .query_row("SELECT * FROM sessions WHERE resume_token = ?1", [token], SqliteDatabase::session_from_row)
+            .optional()?
+            .context("No session has that resume token.")
+    }
+
+    fn remove_session(&mut self, session_id: usize) -> Result<()> {
+        let session = self.get_session(&session_id)?;
+        let mut connection = self.get_lock();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM sessions WHERE id = ?1", [session_id as i64])?;
+        SqliteDatabase::read_room(&transaction, &session.room)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn create_game(&mut self, room: &str, game: &Game) -> Result<usize> {
+        let mut connection = self.get_lock();
+        let transaction = connection.transaction()?;
+        SqliteDatabase::read_room(&transaction, room)?;
+        loop {
+            let game_id = random_id();
+            let taken = transaction
+                .query_row("SELECT 1 FROM games WHERE id = ?1", [game_id as i64], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if taken { continue; }
+
+            SqliteDatabase::insert_game(&transaction, game_id, room, game)?;
+            transaction.execute("UPDATE rooms SET game_id = ?1 WHERE name = ?2", params![game_id as i64, room])?;
+            transaction.commit()?;
+            return Ok(game_id)
+        }
+    }
+
+    fn update_game(&mut self, game_id: usize, game_update: &Game) -> Result<()> {
+        self.modify_game(game_id, |_| game_update.clone())
+            .context(format!("Cannot find game with id '{}'.", game_id))?;
+        Ok(())
+    }
+
+    fn flip_card(&mut self, game_id: usize, coord: (usize, usize)) -> Result<Game> {
+        self.modify_game(game_id, |game| game.flip_card(coord))
+    }
+
+    fn get_game(&self, game_id: usize) -> Result<Game> {
+        SqliteDatabase::read_game(&self.get_lock(), game_id)
+    }
+
+    fn next_turn(&mut self, game_id: usize) -> Result<Game> {
+        self.modify_game(game_id, |game| game.next_turn())
+    }
+
+    fn give_clue(&mut self, game_id: usize, word: &str, count: u8) -> Result<Game> {
+        self.modify_game(game_id, |game| game.give_clue(word, count))
+    }
+
+    fn set_room_words(&mut self, room: &str, words: &[String]) -> Result<()> {
+        let connection = self.get_lock();
+        SqliteDatabase::read_room(&connection, room)?;
+        connection.execute(
+            "INSERT OR REPLACE INTO room_words (room, words) VALUES (?1, ?2)",
+            params![room, serde_json::to_string(words)?],
+        )?;
+        Ok(())
+    }
+
+    fn get_room_words(&self, room: &str) -> Result<Option<Vec<String>>> {
+        let connection = self.get_lock();
+        SqliteDatabase::read_room(&connection, room)?;
+        let words: Option<String> = connection
+            .query_row("SELECT words FROM room_words WHERE room = ?1", [room], |row| row.get(0))
+            .optional()?;
+        words.map(|words| serde_json::from_str(&words).context(format!("Could not parse words for room '{}'.", room)))
+            .transpose()
+    }
+
+    fn log_move(&mut self, game_id: usize, game_move: &Move) -> Result<()> {
+        let connection = self.get_lock();
+        SqliteDatabase::read_game(&connection, game_id)?;
+        connection.execute(
+            "INSERT INTO moves (game_id, entry) VALUES (?1, ?2)",
+            params![game_id as i64, serde_json::to_string(game_move)?],
+        )?;
+        Ok(())
+    }
+
+    fn get_moves(&self, game_id: usize) -> Result<Vec<Move>> {
+        let connection = self.get_lock();
+        SqliteDatabase::read_game(&connection, game_id)?;
+        let mut statement = connection.prepare("SELECT entry FROM moves WHERE game_id = ?1 ORDER BY rowid")?;
+        let entries = statement
+            .query_map([game_id as i64], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        entries.iter()
+            .map(|entry| serde_json::from_str(entry).context(format!("Could not parse a move of game {}.", game_id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{database::Database, game::{Game, GameConfig}, words::WordLists};
+
+    use super::SqliteDatabase;
+
+    #[test]
+    fn persists_between_connections() {
+        let path = std::env::temp_dir().join(format!("codenames-test-{}.db", std::process::id()));
+        let room_name = String::from("foo");
+
+        let game = {
+            let mut db = SqliteDatabase::open(&path).unwrap();
+            db.create_room(&room_name, &Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap()).unwrap();
+            let (session_id, _) = db.create_session(&room_name).unwrap();
+            let room = db.get_room(&room_name).unwrap();
+            assert_eq!(vec![session_id], room.sessions);
+            db.flip_card(room.game_id, (2, 3)).unwrap()
+        };
+
+        // Reopening runs the migrations again, which must leave existing data alone
+        let db = SqliteDatabase::open(&path).unwrap();
+        let room = db.get_room(&room_name).unwrap();
+        assert_eq!(1, room.sessions.len());
+        let restored_game = db.get_game(room.game_id).unwrap();
+        assert!(restored_game.board[2][3].flipped);
+        assert_eq!(game.remaining_cards, restored_game.remaining_cards);
+
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{ensure, Context, Result};
+
+/// Word vectors read from a text file in the GloVe format, one word per line
+/// followed by the numbers of its vector. Words are upper cased to match the
+/// board, and vectors are normalized so their dot product is their cosine similarity.
+#[derive(Debug, Clone)]
+pub struct Embeddings {
+    /// In the order of the file, which for GloVe is most common first.
+    words: Vec<String>,
+    vectors: Vec<Vec<f32>>,
+    index: HashMap<String, usize>,
+}
+
+impl Embeddings {
+    /// Reads at most `max_words` words from the file, so a large file doesn't
+    /// have to fit in memory. Rare words that are left out just can't be clues.
+    pub fn load<P: AsRef<Path>>(path: P, max_words: usize) -> Result<Embeddings> {
+        let path = path.as_ref();
+        let file = File::open(path).context(format!("Could not open word vectors '{}'.", path.display()))?;
+        Embeddings::parse(BufReader::new(file), max_words)
+            .context(format!("Could not read word vectors '{}'.", path.display()))
+    }
+
+    pub fn parse<R: BufRead>(reader: R, max_words: usize) -> Result<Embeddings> {
+        let mut embeddings = Embeddings { words: Vec::new(), vectors: Vec::new(), index: HashMap::new() };
+        for (line_number, line) in reader.lines().enumerate() {
+            if embeddings.words.len() >= max_words {
+                break;
+            }
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let Some(word) = parts.next() else { continue };
+            let vector = parts
+                .map(|part| part.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .context(format!("Line {} has a value that is not a number.", line_number + 1))?;
+            if let Some(first) = embeddings.vectors.first() {
+                ensure!(
+                    vector.len() == first.len(),
+                    "Line {} has {} values, but the first line has {}.",
+                    line_number + 1, vector.len(), first.len()
+                );
+            }
+            let word = word.to_uppercase();
+            if let (false, Some(vector)) = (embeddings.index.contains_key(&word), normalize(vector)) {
+                embeddings.index.insert(word.clone(), embeddings.words.len());
+                embeddings.words.push(word);
+                embeddings.vectors.push(vector);
+            }
+        }
+        ensure!(!embeddings.words.is_empty(), "There are no word vectors.");
+        Ok(embeddings)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The vector for a word in any case. Words with spaces or hyphens that
+    /// aren't in the file, like `ICE CREAM`, get the average of their parts.
+    pub fn vector(&self, word: &str) -> Option<Vec<f32>> {
+        let word = word.to_uppercase();
+        if let Some(&i) = self.index.get(&word) {
+            return Some(self.vectors[i].clone());
+        }
+        let parts = word
+            .split(|c: char| c.is_whitespace() || c == '-')
+            .filter(|part| !part.is_empty())
+            .map(|part| self.index.get(part).map(|&i| &self.vectors[i]))
+            .collect::<Option<Vec<&Vec<f32>>>>()?;
+        if parts.len() < 2 {
+            return None;
+        }
+        let mut sum = vec![0.0; parts[0].len()];
+        for part in parts {
+            sum.iter_mut().zip(part).for_each(|(total, value)| *total += value);
+        }
+        normalize(sum)
+    }
+
+    /// Every word and its vector, most common first.
+    pub fn words(&self) -> impl Iterator<Item = (&str, &[f32])> {
+        self.words.iter().map(|word| word.as_str()).zip(self.vectors.iter().map(|vector| vector.as_slice()))
+    }
+}
+
+/// Cosine similarity of two normalized vectors.
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn normalize(mut vector: Vec<f32>) -> Option<Vec<f32>> {
+    let length = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if vector.is_empty() || length == 0.0 || !length.is_finite() {
+        return None;
+    }
+    vector.iter_mut().for_each(|value| *value /= length);
+    Some(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{similarity, Embeddings};
+
+    #[test]
+    fn parses_glove_text() {
+        let text = "the 1 0 0\nApple 0.5 0.5 0\nzero 0 0 0\napple 0 1 0\npie 0 1 0\nextra 1 1 1\n";
+        let embeddings = Embeddings::parse(text.as_bytes(), 3).unwrap();
+
+        // Zero vectors and repeated words are skipped, and only three words are kept
+        assert_eq!(vec!["THE", "APPLE", "PIE"], embeddings.words().map(|(word, _)| word).collect::<Vec<&str>>());
+        let apple = embeddings.vector("apple").unwrap();
+        assert!((similarity(&apple, &apple) - 1.0).abs() < 1e-6);
+        assert!((similarity(&apple, &embeddings.vector("THE").unwrap()) - 0.5f32.sqrt()).abs() < 1e-6);
+
+        // Words that aren't in the file are made from their parts
+        let apple_pie = embeddings.vector("APPLE PIE").unwrap();
+        assert!(similarity(&apple_pie, &apple) > 0.9);
+        assert!(embeddings.vector("APPLE-CAKE").is_none());
+        assert!(embeddings.vector("CAKE").is_none());
+
+        assert!(Embeddings::parse("the 1 0\napple 1 0 0\n".as_bytes(), 10).is_err());
+        assert!(Embeddings::parse("the 1 x\n".as_bytes(), 10).is_err());
+        assert!(Embeddings::parse("".as_bytes(), 10).is_err());
+    }
+}
@@ -1,10 +1,11 @@
-use actix::{Addr, Message};
+use actix::Message;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    client::{WsClient, ClientSession},
-    database::Database,
-    game::{Card, Game},
+    bot::BotRole,
+    client::{ClientAddr, ClientSession},
+    game::{CardView, Clue, GameConfig, GameView, Team},
+    server::ServerError,
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,13 +22,126 @@ pub enum Event {
         text: String,
     },
     // Game events
+    /// The card as everyone sees it after the guess, which in Duet games may
+    /// only have been marked as a bystander for one side.
     #[serde(rename_all = "camelCase")]
-    FlipCard { flipped_card: Card },
+    FlipCard { flipped_card: CardView },
     NewGame {},
-    GameStateUpdate { game: Game },
+    /// `game_id` identifies the game for exporting it once it's over.
+    #[serde(rename_all = "camelCase")]
+    GameStateUpdate { game_id: usize, game: GameView },
     UpdateClientSession { session: ClientSession },
     SetSpyMaster {},
-    NextTurn {}
+    NextTurn {},
+    JoinTeam { id: usize, team: Team },
+    GiveClue { clue: Clue },
+    #[serde(rename_all = "camelCase")]
+    UploadWords { word_count: usize },
+    /// The card that was turned back over by undoing its flip.
+    Undo { card: CardView },
+    /// A player asked to undo the last flip in a room where undoing takes a vote.
+    UndoVote { id: usize, votes: usize, needed: usize },
+    /// Sent only to the player who owns the session, who can send it back in a
+    /// `Resume` request after reconnecting.
+    ResumeToken { token: String },
+    /// Who hosts the room and whether it is locked, sent whenever either changes.
+    RoomState { host: Option<usize>, locked: bool },
+    /// The host removed a player from the room.
+    Kick { id: usize },
+    /// Sent only to the client whose request was rejected.
+    Error { code: ErrorCode, message: String },
+    TeamRosters {
+        red: Vec<ClientSession>,
+        blue: Vec<ClientSession>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        green: Vec<ClientSession>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        yellow: Vec<ClientSession>,
+        unassigned: Vec<ClientSession>,
+    }
+}
+
+/// Reasons the server can refuse to carry out a request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    GameOver,
+    NoTeam,
+    NotYourTurn,
+    SpymasterCannotFlip,
+    /// The team asked for isn't one of the teams playing the game.
+    TeamNotInGame,
+    /// Duet: the player who gave the clue tried to guess it.
+    CannotGuessOwnClue,
+    CardAlreadyFlipped,
+    InvalidCard,
+    NotSpymaster,
+    NoClue,
+    ClueAlreadyGiven,
+    InvalidClue,
+    ClueWordOnBoard,
+    InvalidGameConfig,
+    UnknownWordList,
+    InvalidWordList,
+    RoomNotFound,
+    SessionNotFound,
+    InvalidRequest,
+    InvalidResumeToken,
+    NothingToUndo,
+    UndoNotAllowed,
+    GameNotFound,
+    GameNotFinished,
+    WrongPassword,
+    NotHost,
+    RoomLocked,
+    PlayerNotFound,
+    NotAdmin,
+    BotsUnavailable,
+    ServerError,
+}
+
+impl ErrorCode {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::GameOver => "The game is over.",
+            ErrorCode::NoTeam => "You need to join a team first.",
+            ErrorCode::NotYourTurn => "It is not your team's turn.",
+            ErrorCode::SpymasterCannotFlip => "Spymasters cannot flip cards.",
+            ErrorCode::TeamNotInGame => "That team is not playing this game.",
+            ErrorCode::CannotGuessOwnClue => "The other player has to guess your clue.",
+            ErrorCode::CardAlreadyFlipped => "That card has already been flipped.",
+            ErrorCode::InvalidCard => "That card is not on the board.",
+            ErrorCode::NotSpymaster => "Only spymasters can give clues.",
+            ErrorCode::NoClue => "Wait for your spymaster to give a clue.",
+            ErrorCode::ClueAlreadyGiven => "A clue has already been given this turn.",
+            ErrorCode::InvalidClue => "Clues must be a single word.",
+            ErrorCode::ClueWordOnBoard => "Clues cannot be a word that is on the board.",
+            ErrorCode::InvalidGameConfig => "That game config is not possible.",
+            ErrorCode::UnknownWordList => "There is no word list with that name.",
+            ErrorCode::InvalidWordList => "That word list cannot be used.",
+            ErrorCode::RoomNotFound => "That room does not exist.",
+            ErrorCode::SessionNotFound => "Your session has ended, reconnect to keep playing.",
+            ErrorCode::InvalidRequest => "The server could not understand that request.",
+            ErrorCode::InvalidResumeToken => "That session has expired or belongs to another room.",
+            ErrorCode::NothingToUndo => "There is no card flip to undo.",
+            ErrorCode::UndoNotAllowed => "Only spymasters can undo card flips.",
+            ErrorCode::GameNotFound => "That game does not exist.",
+            ErrorCode::GameNotFinished => "Games can only be exported once they are over.",
+            ErrorCode::WrongPassword => "That room is private and the password is wrong.",
+            ErrorCode::NotHost => "Only the host can do that.",
+            ErrorCode::RoomLocked => "That room is locked, ask the host to unlock it.",
+            ErrorCode::PlayerNotFound => "That player is not in this room.",
+            ErrorCode::NotAdmin => "That needs the server's admin token.",
+            ErrorCode::BotsUnavailable => "This server has no word vectors for bots to play with.",
+            ErrorCode::ServerError => "Something went wrong on the server, try again.",
+        }
+    }
+}
+
+impl From<ErrorCode> for Event {
+    fn from(code: ErrorCode) -> Self {
+        Event::Error { message: code.message().to_string(), code }
+    }
 }
 
 #[derive(Message, Serialize, Deserialize, Debug, Clone)]
@@ -49,9 +163,62 @@ pub enum ClientRequestType {
     TimedOut { id: usize },
     Message { text: String },
     FlipCard { coord: (usize, usize) },
-    NewGame {},
+    /// Starts a new game, keeping the current game's config and word list if none are given.
+    /// Games started with the same seed, config and word list are identical.
+    #[serde(rename_all = "camelCase")]
+    NewGame { config: Option<GameConfig>, word_list: Option<String>, seed: Option<u64> },
     SetSpyMaster { spymaster: bool },
-    NextTurn {}
+    NextTurn {},
+    /// Joins the given team, or whichever team has fewer players if no team is given.
+    JoinTeam { team: Option<Team> },
+    GiveClue { word: String, count: u8 },
+    /// Replaces the room's custom word list, used by new games with the `custom` word list.
+    UploadWords { words: Vec<String> },
+    /// Moves this connection over to the session the resume token was issued for,
+    /// keeping its name, team and spymaster status.
+    Resume { token: String },
+    /// Undoes the last card flip this turn, or votes to if the game's config asks for a vote.
+    Undo {},
+    // Host only requests
+    /// Removes another player from the room.
+    Kick { id: usize },
+    /// Makes another player the host.
+    TransferHost { id: usize },
+    /// Locked rooms only let in players resuming a session they already had.
+    LockRoom { locked: bool },
+    /// Adds a bot to the given team, or whichever team has fewer players. Bots
+    /// are spymasters unless another role is given.
+    AddBot {
+        team: Option<Team>,
+        #[serde(default)]
+        role: BotRole,
+    },
+}
+
+impl ClientRequestType {
+    /// The request's type as it is sent over the wire, like `flipCard`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientRequestType::Connect { .. } => "connect",
+            ClientRequestType::SetName { .. } => "setName",
+            ClientRequestType::Disconnect { .. } => "disconnect",
+            ClientRequestType::TimedOut { .. } => "timedOut",
+            ClientRequestType::Message { .. } => "message",
+            ClientRequestType::FlipCard { .. } => "flipCard",
+            ClientRequestType::NewGame { .. } => "newGame",
+            ClientRequestType::SetSpyMaster { .. } => "setSpyMaster",
+            ClientRequestType::NextTurn {} => "nextTurn",
+            ClientRequestType::JoinTeam { .. } => "joinTeam",
+            ClientRequestType::GiveClue { .. } => "giveClue",
+            ClientRequestType::UploadWords { .. } => "uploadWords",
+            ClientRequestType::Resume { .. } => "resume",
+            ClientRequestType::Undo {} => "undo",
+            ClientRequestType::Kick { .. } => "kick",
+            ClientRequestType::TransferHost { .. } => "transferHost",
+            ClientRequestType::LockRoom { .. } => "lockRoom",
+            ClientRequestType::AddBot { .. } => "addBot",
+        }
+    }
 }
 
 #[derive(Message, Serialize, Deserialize, Debug, Clone)]
@@ -64,8 +231,50 @@ pub struct ClientRequest {
 }
 
 #[derive(Message)]
-#[rtype("usize")]
-pub struct NewClientConnection<T: 'static + Database + std::marker::Unpin> {
+#[rtype(result = "Result<usize, ServerError>")]
+pub struct NewClientConnection {
+    pub room: String,
+    pub addr: ClientAddr,
+    /// Hash of the password the player gave, already checked against the room's.
+    /// Sets the room's password when the room is created.
+    pub password_hash: Option<String>,
+    /// Resume token the player is about to send, which lets them back into a locked room.
+    pub resume_token: Option<String>,
+}
+
+/// Sent by a client when its websocket closes. The session is kept for a grace
+/// period in case the player resumes it from a new connection.
+#[derive(Message)]
+#[rtype("()")]
+pub struct ClientDisconnected {
+    pub session_id: usize,
+    pub room: String,
+    pub addr: ClientAddr,
+    pub timed_out: bool,
+}
+
+/// Instructions from the server to a client actor that aren't passed on to the player.
+#[derive(Message, Debug, Clone, PartialEq)]
+#[rtype("()")]
+pub enum ClientControl {
+    /// The connection now belongs to the resumed session with this id.
+    Reassign { session_id: usize },
+    /// Another connection resumed this session, so this one closes.
+    Replaced,
+    /// The host removed the player from the room.
+    Kicked,
+    /// The room was deleted through the admin API.
+    RoomClosed,
+}
+
+/// Brings the server's metrics up to date and renders them for Prometheus.
+#[derive(Message, Debug, Clone)]
+#[rtype(result = "Result<String, ServerError>")]
+pub struct ScrapeMetrics;
+
+/// Disconnects everyone in a room and deletes it.
+#[derive(Message, Debug, Clone)]
+#[rtype(result = "Result<(), ServerError>")]
+pub struct CloseRoom {
     pub room: String,
-    pub addr: Addr<WsClient<T>>,
 }
@@ -1,51 +1,89 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader}, vec,
-};
-
+use anyhow::{Context, Result, bail, ensure};
 use log::{debug};
-use rand::{prelude::SliceRandom, Rng};
+use rand::{prelude::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 
+use crate::{client::ClientSession, words::{WordList, WordLists}};
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum CardType {
     RED,
     BLUE,
+    GREEN,
+    YELLOW,
     BYSTANDER,
     ASSASSIN,
+    /// An agent in Duet games, which both players are looking for.
+    AGENT,
 }
 
 impl CardType {
-    fn from_team(team: &Team) -> CardType {
+    pub fn from_team(team: &Team) -> CardType {
         match team {
             Team::BLUE => CardType::BLUE,
-            Team::RED => CardType::RED
+            Team::RED => CardType::RED,
+            Team::GREEN => CardType::GREEN,
+            Team::YELLOW => CardType::YELLOW,
+        }
+    }
+
+    /// The team the card belongs to, if it's one of a team's cards.
+    pub fn team(&self) -> Option<Team> {
+        match self {
+            CardType::BLUE => Some(Team::BLUE),
+            CardType::RED => Some(Team::RED),
+            CardType::GREEN => Some(Team::GREEN),
+            CardType::YELLOW => Some(Team::YELLOW),
+            _ => None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Team {
     RED,
     BLUE,
+    GREEN,
+    YELLOW,
 }
 
 impl Team {
-    pub fn opposite(team: &Team) -> Team {
-        match team {
-            Team::RED => Team::BLUE,
-            Team::BLUE => Team::RED,
+    /// Every team, in the order of `Game::remaining_cards`. A game with `n` teams
+    /// is played by the first `n`.
+    pub const ALL: [Team; 4] = [Team::BLUE, Team::RED, Team::GREEN, Team::YELLOW];
+
+    /// Where the team's count is in `Game::remaining_cards`.
+    pub fn index(&self) -> usize {
+        match self {
+            Team::BLUE => 0,
+            Team::RED => 1,
+            Team::GREEN => 2,
+            Team::YELLOW => 3,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Card {
     pub word: String,
+    /// In Duet games, the card's type on the red player's side of the key.
     pub card_type: CardType,
     pub flipped: bool,
-    pub coord: (usize, usize)
+    pub coord: (usize, usize),
+    /// Duet only: the card's type on the blue player's side of the key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub back_type: Option<CardType>,
+    /// Duet only: the side of the key the card was flipped by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flipped_for: Option<Team>,
+    /// Duet only: the sides whose clue led to the card being guessed as a
+    /// bystander. It stays in play for clues from the other side.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bystander_for: Vec<Team>,
 }
 
 impl Card {
@@ -54,7 +92,32 @@ impl Card {
             word,
             card_type,
             flipped: false,
-            coord
+            coord,
+            back_type: None,
+            flipped_for: None,
+            bystander_for: Vec::new(),
+        }
+    }
+
+    /// The card's type on one side of a Duet key, red being the front.
+    pub fn key_for(&self, side: &Team) -> &CardType {
+        match (side, &self.back_type) {
+            (Team::BLUE, Some(back_type)) => back_type,
+            _ => &self.card_type,
+        }
+    }
+
+    /// Whether the card can still be guessed for a clue from `side`.
+    pub fn in_play_for(&self, side: &Team) -> bool {
+        !self.flipped && !self.bystander_for.contains(side)
+    }
+
+    /// What everyone has seen of the card from guesses: its type if it was
+    /// flipped, and in Duet games the side of the key it was flipped by.
+    fn revealed_type(&self) -> Option<CardType> {
+        match &self.flipped_for {
+            Some(side) => Some(self.key_for(side).clone()),
+            None => self.flipped.then(|| self.card_type.clone()),
         }
     }
 }
@@ -65,209 +128,686 @@ impl Default for Card {
     }
 }
 
-pub type Board = [[Card; 5]; 5];
+/// Cards indexed by `[row][col]`. Every row has the same length.
+pub type Board = Vec<Vec<Card>>;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The shape of the board and how many of each card type it holds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GameConfig {
+    pub rows: usize,
+    pub cols: usize,
+    pub starting_team_cards: usize,
+    /// Cards for each team besides the starting team.
+    pub other_team_cards: usize,
+    pub assassins: usize,
+    pub bystanders: usize,
+    #[serde(default)]
+    pub undo: UndoPermission,
+    #[serde(default)]
+    pub mode: GameMode,
+    /// How many teams play, taking turns in the order of `Team::ALL`.
+    #[serde(default = "default_teams")]
+    pub teams: usize,
+}
+
+fn default_teams() -> usize {
+    2
+}
+
+/// The rules a game is played by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameMode {
+    /// Two teams race to find their own cards.
+    #[default]
+    Classic,
+    /// Two players, red and blue, work together to find the agents on both sides
+    /// of a shared key, taking turns to give clues from their own side. They
+    /// lose if they flip an assassin or run out of timer tokens, one of which is
+    /// used up every turn. The card counts of the config are ignored, the Duet
+    /// key always has 9 agents and 3 assassins on each side.
+    #[serde(rename_all = "camelCase")]
+    Duet { timer_tokens: u8 },
+}
+
+/// The most rows or columns a board can have.
+const MAX_BOARD_SIDE: usize = 10;
+
+/// Agents on each side of a Duet key.
+const DUET_AGENTS: u8 = 9;
+
+/// Who is allowed to take back a card flip.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum UndoPermission {
+    #[default]
+    Anyone,
+    Spymasters,
+    /// The flip is undone once more than half of the room asks for it.
+    Vote,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            rows: 5,
+            cols: 5,
+            starting_team_cards: 9,
+            other_team_cards: 8,
+            assassins: 1,
+            bystanders: 7,
+            undo: UndoPermission::default(),
+            mode: GameMode::default(),
+            teams: default_teams(),
+        }
+    }
+}
+
+impl GameConfig {
+    /// Saturates instead of overflowing, `validate` rejects boards that big.
+    pub fn card_count(&self) -> usize {
+        self.rows.saturating_mul(self.cols)
+    }
+
+    /// The teams playing, in turn order.
+    pub fn teams(&self) -> &[Team] {
+        &Team::ALL[..self.teams.min(Team::ALL.len())]
+    }
+
+    /// The standard three team game on a 6x6 board.
+    pub fn three_teams() -> GameConfig {
+        GameConfig {
+            rows: 6,
+            cols: 6,
+            starting_team_cards: 9,
+            other_team_cards: 8,
+            assassins: 1,
+            bystanders: 10,
+            teams: 3,
+            ..GameConfig::default()
+        }
+    }
+
+    /// The standard Duet game, with 9 timer tokens.
+    pub fn duet() -> GameConfig {
+        GameConfig { mode: GameMode::Duet { timer_tokens: 9 }, ..GameConfig::default() }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.rows > 0 && self.cols > 0, "The board needs at least one row and one column.");
+        ensure!(
+            self.rows <= MAX_BOARD_SIDE && self.cols <= MAX_BOARD_SIDE,
+            "Boards can have at most {} rows and {} columns.", MAX_BOARD_SIDE, MAX_BOARD_SIDE
+        );
+        ensure!(
+            (2..=Team::ALL.len()).contains(&self.teams),
+            "Games are played by 2 to {} teams, not {}.", Team::ALL.len(), self.teams
+        );
+        if let GameMode::Duet { timer_tokens } = self.mode {
+            ensure!(self.teams == 2, "Duet is played by two players.");
+            ensure!(self.card_count() == 25, "Duet is played on 25 cards, but a {}x{} board has {}.", self.rows, self.cols, self.card_count());
+            let default = GameConfig::default();
+            ensure!(
+                (self.starting_team_cards, self.other_team_cards, self.assassins, self.bystanders)
+                    == (default.starting_team_cards, default.other_team_cards, default.assassins, default.bystanders),
+                "Duet games always use the same key, so their card counts can't be changed."
+            );
+            ensure!(timer_tokens > 0, "Duet games need at least one timer token.");
+            return Ok(());
+        }
+        // The counts come from players, so they can be anything
+        let cards = self.other_team_cards
+            .checked_mul(self.teams - 1)
+            .and_then(|cards| cards.checked_add(self.starting_team_cards))
+            .and_then(|cards| cards.checked_add(self.assassins))
+            .and_then(|cards| cards.checked_add(self.bystanders))
+            .context(format!("A {}x{} board has {} cards, but the card counts add up to more.", self.rows, self.cols, self.card_count()))?;
+        ensure!(
+            cards == self.card_count(),
+            "A {}x{} board has {} cards, but the card counts add up to {}.",
+            self.rows, self.cols, self.card_count(), cards
+        );
+        ensure!(
+            self.starting_team_cards > 0 && self.other_team_cards > 0,
+            "Every team needs at least one card."
+        );
+        ensure!(
+            self.starting_team_cards <= u8::MAX as usize && self.other_team_cards <= u8::MAX as usize,
+            "Teams can have at most {} cards.", u8::MAX
+        );
+        Ok(())
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type", content = "data")]
 pub enum GameStatus {
     PLAYING {},
-    OVER { winner: Team }
+    OVER { winner: Team },
+    /// Duet: every agent was found.
+    WON {},
+    /// Duet: everyone lost together.
+    LOST { reason: LossReason },
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl GameStatus {
+    pub fn is_over(&self) -> bool {
+        !matches!(self, GameStatus::PLAYING {})
+    }
+}
+
+/// Why a Duet game was lost.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LossReason {
+    Assassin,
+    /// The timer tokens ran out before every agent was found.
+    OutOfTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Clue {
+    pub team: Team,
+    pub word: String,
+    pub count: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Game {
+    #[serde(default)]
+    pub config: GameConfig,
+    /// Name of the word list the board was drawn from.
+    #[serde(default = "default_word_list")]
+    pub word_list: String,
+    /// Seed the board was generated from. The same seed, config and word list
+    /// always produce the same board.
+    #[serde(default)]
+    pub seed: u64,
     pub starting_team: Team,
     pub turn_team: Team,
     pub board: Board,
-    pub remaining_cards: (u8, u8),
-    pub game_status: GameStatus
+    /// Unflipped cards of each team, indexed by `Team::index`.
+    pub remaining_cards: Vec<u8>,
+    pub game_status: GameStatus,
+    /// Teams that flipped an assassin and are out of the game, in games with
+    /// more than two teams. The turn skips them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub eliminated: Vec<Team>,
+    /// Every clue given so far, oldest first.
+    #[serde(default)]
+    pub clues: Vec<Clue>,
+    /// Flips the turn team has left this turn, `None` until their spymaster gives a clue.
+    #[serde(default)]
+    pub guesses_remaining: Option<u8>,
+    /// Duet only: turns left before time runs out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timer_tokens: Option<u8>,
+}
+
+/// A card as one player sees it: the card type stays hidden until the card is
+/// flipped unless the player is allowed to see the key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CardView {
+    pub word: String,
+    pub card_type: Option<CardType>,
+    pub flipped: bool,
+    pub coord: (usize, usize),
+    /// Duet only, see `Card::bystander_for`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bystander_for: Vec<Team>,
+}
+
+/// The state of a game as one player sees it. The only way to get one from a
+/// `Game` is through `Game::view_for`, so the key is never sent unredacted by accident.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GameView {
+    pub mode: GameMode,
+    /// Only for players who can see the whole key, since the seed deals it again.
+    pub seed: Option<u64>,
+    pub starting_team: Team,
+    pub turn_team: Team,
+    pub board: Vec<Vec<CardView>>,
+    pub remaining_cards: Vec<u8>,
+    pub game_status: GameStatus,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub eliminated: Vec<Team>,
+    pub clues: Vec<Clue>,
+    pub guesses_remaining: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timer_tokens: Option<u8>,
+}
+
+fn default_word_list() -> String {
+    WordLists::DEFAULT.to_string()
 }
 
 impl Game {
-    pub fn new() -> Self {
-        let starting_team = Team::BLUE;
-        return Game {
-            board: Game::create_board(&starting_team),
-            turn_team: starting_team.clone(),
-            remaining_cards: Game::initalize_remaining_cards(&starting_team),
-            starting_team,
-            game_status: GameStatus::PLAYING {  }
+    /// Creates a game entirely determined by its seed, including which team starts,
+    /// so every room using the same seed, config and word list plays the same game.
+    pub fn new(config: &GameConfig, words: &WordList, seed: u64) -> Result<Self> {
+        config.validate()?;
+        Game::deal(config, words, seed)
+    }
+
+    /// `Game::new` for a config that's already been validated.
+    fn deal(config: &GameConfig, words: &WordList, seed: u64) -> Result<Self> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        // Two team games keep drawing the starting team the same way, so seeds
+        // from before there were more teams still deal the same games
+        let teams = config.teams();
+        let starting_team = match teams.len() {
+            2 => if rng.gen() { Team::BLUE } else { Team::RED },
+            count => teams[rng.gen_range(0..count)].clone(),
         };
+        Game::with_starting_team(config, words, starting_team, seed, &mut rng)
+    }
+
+    /// Starts the next game in a room. Without a seed, the team after the one
+    /// that went first last game goes first on a random board.
+    pub fn new_from_game(game: &Game, config: &GameConfig, words: &WordList, seed: Option<u64>) -> Result<Self> {
+        config.validate()?;
+        match seed {
+            Some(seed) => Game::deal(config, words, seed),
+            None => {
+                let seed = Game::random_seed();
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                let teams = config.teams();
+                let last_start = teams.iter().position(|team| *team == game.starting_team).unwrap_or_else(|| teams.len() - 1);
+                let starting_team = teams[(last_start + 1) % teams.len()].clone();
+                Game::with_starting_team(config, words, starting_team, seed, &mut rng)
+            }
+        }
+    }
+
+    pub fn new_from_current_game(&self, config: &GameConfig, words: &WordList, seed: Option<u64>) -> Result<Self> {
+        Game::new_from_game(self, config, words, seed)
+    }
+
+    /// A random seed that survives a round trip through a JavaScript number.
+    pub fn random_seed() -> u64 {
+        rand::thread_rng().gen_range(0..(1 << 53))
     }
 
-    pub fn new_from_game(game: &Game) -> Self {
-        let starting_team = Team::opposite(&game.starting_team);
-        return Game {
-            board: Game::create_board(&starting_team),
+    fn with_starting_team<R: Rng>(
+        config: &GameConfig,
+        words: &WordList,
+        starting_team: Team,
+        seed: u64,
+        rng: &mut R
+    ) -> Result<Self> {
+        let (key, remaining_cards, timer_tokens) = match config.mode {
+            GameMode::Classic => (
+                Game::card_types(config, &starting_team).into_iter().map(|card_type| (card_type, None)).collect(),
+                Game::initalize_remaining_cards(config, &starting_team),
+                None,
+            ),
+            GameMode::Duet { timer_tokens } => (Game::duet_key(), vec![DUET_AGENTS, DUET_AGENTS], Some(timer_tokens)),
+        };
+        Ok(Game {
+            board: Game::create_board(config, &words.words, key, rng)?,
+            config: config.clone(),
+            word_list: words.name.clone(),
+            seed,
             turn_team: starting_team.clone(),
-            remaining_cards: Game::initalize_remaining_cards(&starting_team),
+            remaining_cards,
             starting_team,
-            game_status: GameStatus::PLAYING {  }
-        };
+            game_status: GameStatus::PLAYING {  },
+            eliminated: Vec::new(),
+            clues: Vec::new(),
+            guesses_remaining: None,
+            timer_tokens,
+        })
     }
 
-    pub fn new_from_current_game(&self) -> Self {
-        Game::new_from_game(self)
+    fn initalize_remaining_cards(config: &GameConfig, starting_team: &Team) -> Vec<u8> {
+        config.teams()
+            .iter()
+            .map(|team| if team == starting_team { config.starting_team_cards } else { config.other_team_cards } as u8)
+            .collect()
     }
 
-    fn initalize_remaining_cards(starting_team: &Team) -> (u8, u8) {
-        match starting_team {
-            Team::BLUE => (9, 8),
-            Team::RED => (8, 9)
+    /// Every card type on the board, in no particular order.
+    fn card_types(config: &GameConfig, starting_team: &Team) -> Vec<CardType> {
+        let mut card_types = Vec::with_capacity(config.card_count());
+        card_types.extend(std::iter::repeat_n(CardType::from_team(starting_team), config.starting_team_cards));
+        for team in config.teams().iter().filter(|team| *team != starting_team) {
+            card_types.extend(std::iter::repeat_n(CardType::from_team(team), config.other_team_cards));
         }
+        card_types.extend(std::iter::repeat_n(CardType::ASSASSIN, config.assassins));
+        card_types.extend(std::iter::repeat_n(CardType::BYSTANDER, config.bystanders));
+        card_types
     }
 
-    fn fill_card(board: &mut Board, card_type: &CardType) {
-        let mut rng = rand::thread_rng();
-        let mut card: &mut Card;
-        card = &mut board[rng.gen_range(0..5)][rng.gen_range(0..5)];
-        loop {
-            match card.card_type {
-                CardType::BYSTANDER => {
-                    card.card_type = card_type.clone();
-                    break;
-                }
-                _ => {
-                    debug!("Card already taken trying again");
-                    card = &mut board[rng.gen_range(0..5)][rng.gen_range(0..5)];
-                }
-            }
-        }
+    /// The two sides of the Duet key card, red's side first. Each side has 9
+    /// agents and 3 assassins, and 3 agents are agents on both sides.
+    fn duet_key() -> Vec<(CardType, Option<CardType>)> {
+        use CardType::{AGENT, ASSASSIN, BYSTANDER};
+        let pairs = [
+            (AGENT, AGENT, 3),
+            (AGENT, BYSTANDER, 5),
+            (AGENT, ASSASSIN, 1),
+            (BYSTANDER, AGENT, 5),
+            (ASSASSIN, AGENT, 1),
+            (ASSASSIN, ASSASSIN, 1),
+            (ASSASSIN, BYSTANDER, 1),
+            (BYSTANDER, ASSASSIN, 1),
+            (BYSTANDER, BYSTANDER, 7),
+        ];
+        pairs.into_iter()
+            .flat_map(|(front, back, count)| std::iter::repeat_n((front, Some(back)), count))
+            .collect()
     }
 
-    fn create_board(starting_team: &Team) -> Board {
-        let words = Game::get_words();
+    /// Deals a board by drawing words without replacement and shuffling the key,
+    /// the card types and in Duet games their back sides, over them. `words` must
+    /// not contain duplicates, which `WordList` ensures.
+    fn create_board<R: Rng>(
+        config: &GameConfig,
+        words: &[String],
+        mut key: Vec<(CardType, Option<CardType>)>,
+        rng: &mut R,
+    ) -> Result<Board> {
+        if words.len() < config.card_count() {
+            bail!("The word list only has {} words, but the board needs {}.", words.len(), config.card_count())
+        }
 
-        let mut board: Board = Default::default();
-        let mut chosen_words: Vec<String> = vec![];
-        for row in 0..5 {
-            for col in 0..5 {
-                let mut random_word = words.choose(&mut rand::thread_rng()).unwrap().clone();
-                loop {
-                    if !chosen_words.contains(&random_word) {
-                        board[row][col] = Card::new(random_word.clone(), CardType::BYSTANDER, (row, col));
-                        chosen_words.push(random_word);
-                        break
-                    };
+        key.shuffle(rng);
 
-                    debug!("Word already taken, trying again");
-                    random_word = words.choose(&mut rand::thread_rng()).unwrap().clone();
-                }
-            }
-        }
+        let mut cards = words
+            .choose_multiple(rng, config.card_count())
+            .zip(key)
+            .enumerate()
+            .map(|(i, (word, (card_type, back_type)))| Card {
+                back_type,
+                ..Card::new(word.clone(), card_type, (i / config.cols, i % config.cols))
+            });
 
-        for i in 0..9 {
-            // Assign starting team
-            Game::fill_card(&mut board, &CardType::from_team(starting_team));
+        Ok((0..config.rows)
+            .map(|_| cards.by_ref().take(config.cols).collect())
+            .collect())
+    }
 
-            // Assign opposite team (second team has -1 card)
-            if i != 8 {
-                Game::fill_card(
-                    &mut board,
-                    &CardType::from_team(&Team::opposite(starting_team)),
-                )
-            }
+    /// Projects the game for a single player. Spymasters see the whole key, everyone
+    /// else only sees the type of cards that have been flipped until the game is over.
+    /// In Duet games, players see their own team's side of the key instead.
+    pub fn view_for(&self, session: &ClientSession) -> GameView {
+        let key_side = match self.config.mode {
+            GameMode::Classic => (session.is_spymaster || self.game_status.is_over()).then_some(Team::RED),
+            GameMode::Duet { .. } => session.team.clone(),
+        };
+        // The seed deals the whole key again, in Duet games both sides of it
+        let show_seed = match self.config.mode {
+            GameMode::Classic => key_side.is_some(),
+            GameMode::Duet { .. } => self.game_status.is_over(),
+        };
+        let board = self.board.iter()
+            .map(|row| row.iter()
+                .map(|card| CardView {
+                    word: card.word.clone(),
+                    card_type: match &key_side {
+                        Some(side) => Some(card.key_for(side).clone()),
+                        None => card.revealed_type(),
+                    },
+                    flipped: card.flipped,
+                    coord: card.coord,
+                    bystander_for: card.bystander_for.clone(),
+                })
+                .collect())
+            .collect();
+
+        GameView {
+            mode: self.config.mode.clone(),
+            seed: show_seed.then_some(self.seed),
+            starting_team: self.starting_team.clone(),
+            turn_team: self.turn_team.clone(),
+            board,
+            remaining_cards: self.remaining_cards.clone(),
+            game_status: self.game_status.clone(),
+            eliminated: self.eliminated.clone(),
+            clues: self.clues.clone(),
+            guesses_remaining: self.guesses_remaining,
+            timer_tokens: self.timer_tokens,
         }
+    }
 
-        // Assign assassin
-        Game::fill_card(&mut board, &CardType::ASSASSIN);
-        board
+    /// A card as everyone sees it after it's been guessed.
+    pub fn revealed_card(&self, coord: (usize, usize)) -> Option<CardView> {
+        let card = self.board.get(coord.0)?.get(coord.1)?;
+        Some(CardView {
+            word: card.word.clone(),
+            card_type: card.revealed_type().or_else(|| {
+                card.bystander_for.last().map(|side| card.key_for(side).clone())
+            }),
+            flipped: card.flipped,
+            coord: card.coord,
+            bystander_for: card.bystander_for.clone(),
+        })
     }
 
-    fn get_words() -> Vec<String> {
-        let file = File::open("./src/words.txt").expect("Unable to open file");
-        let buf = BufReader::new(file);
-        buf.lines()
-            .map(|l| l.expect("Could not parse line from file"))
+    /// Unflipped agents on each side of a Duet key, in the same order as `remaining_cards`.
+    fn duet_remaining_agents(&self) -> Vec<u8> {
+        self.config.teams()
+            .iter()
+            .map(|side| self.board.iter()
+                .flatten()
+                .filter(|card| !card.flipped && *card.key_for(side) == CardType::AGENT)
+                .count() as u8)
             .collect()
     }
 
+    pub fn remaining_for(&self, team: &Team) -> u8 {
+        self.remaining_cards.get(team.index()).copied().unwrap_or(0)
+    }
+
+    /// The team that plays after `team`, skipping teams that are out of the game.
+    pub fn next_team(&self, team: &Team) -> Team {
+        let teams = self.config.teams();
+        let position = teams.iter().position(|other| other == team).unwrap_or(0);
+        (1..=teams.len())
+            .map(|offset| &teams[(position + offset) % teams.len()])
+            .find(|other| !self.eliminated.contains(other))
+            .unwrap_or(team)
+            .clone()
+    }
+
+    /// Whether `word` matches one of the cards still face down on the board.
+    pub fn is_unflipped_word(&self, word: &str) -> bool {
+        self.board.iter()
+            .flatten()
+            .any(|card| !card.flipped && card.word.eq_ignore_ascii_case(word))
+    }
+
+    /// Records a clue for the turn team and gives them `count + 1` guesses.
+    pub fn give_clue(&self, word: &str, count: u8) -> Game {
+        let mut new_game = self.clone();
+        new_game.clues.push(Clue {
+            team: new_game.turn_team.clone(),
+            word: word.to_string(),
+            count,
+        });
+        new_game.guesses_remaining = Some(count.saturating_add(1));
+        new_game
+    }
+
     pub fn flip_card(&self, coord: (usize, usize)) -> Game {
+        if let GameMode::Duet { .. } = self.config.mode {
+            return self.flip_duet_card(coord);
+        }
         let mut new_game = self.clone();
+        let turn_team = new_game.turn_team.clone();
         let card = &mut new_game.board[coord.0][coord.1];
+        card.flipped = true;
+        let card_type = card.card_type.clone();
 
-        match card.card_type {
-            CardType::BLUE => {
-                new_game.remaining_cards.0 -= 1;
+        match card_type.team() {
+            Some(owner) => {
+                if let Some(remaining) = new_game.remaining_cards.get_mut(owner.index()) {
+                    *remaining = remaining.saturating_sub(1);
+                }
 
-                if new_game.remaining_cards.0 == 0 {
-                    new_game.game_status = GameStatus::OVER { winner: Team::BLUE }
+                // A team that is out of the game can't win by having its cards found for it
+                if new_game.remaining_for(&owner) == 0 && !new_game.eliminated.contains(&owner) {
+                    new_game.game_status = GameStatus::OVER { winner: owner.clone() };
                 }
 
-                if let Team::RED = new_game.turn_team {
-                    new_game.turn_team = Team::opposite(&new_game.turn_team);
+                if owner != turn_team {
+                    new_game.turn_team = new_game.next_team(&turn_team);
+                }
+            },
+            None if card_type == CardType::ASSASSIN => {
+                // The team is out, and the game is over once a single team is left
+                new_game.eliminated.push(turn_team.clone());
+                let mut left = new_game.config.teams().iter().filter(|team| !new_game.eliminated.contains(team));
+                match (left.next(), left.next()) {
+                    (Some(winner), None) => new_game.game_status = GameStatus::OVER { winner: winner.clone() },
+                    _ => new_game.turn_team = new_game.next_team(&turn_team),
                 }
             },
-            CardType::RED => {
-                new_game.remaining_cards.1 -= 1;
+            None => new_game.turn_team = new_game.next_team(&turn_team),
+        };
 
-                if new_game.remaining_cards.1 == 0 {
-                    new_game.game_status = GameStatus::OVER { winner: Team::RED };
+        if new_game.turn_team == turn_team {
+            if let Some(guesses) = new_game.guesses_remaining.as_mut() {
+                *guesses = guesses.saturating_sub(1);
+                if *guesses == 0 && matches!(new_game.game_status, GameStatus::PLAYING {}) {
+                    debug!("Team {:?} is out of guesses", turn_team);
+                    return new_game.next_turn();
                 }
+            }
+        } else {
+            new_game.guesses_remaining = None;
+        }
+
+        new_game
+    }
+
+    /// In Duet games the turn team gave the clue, so the card is judged by their
+    /// side of the key. Operatives can keep guessing until they miss.
+    fn flip_duet_card(&self, coord: (usize, usize)) -> Game {
+        let mut new_game = self.clone();
+        let side = new_game.turn_team.clone();
+        let card = &mut new_game.board[coord.0][coord.1];
 
-                if let Team::BLUE = new_game.turn_team {
-                    new_game.turn_team = Team::opposite(&new_game.turn_team);
+        match card.key_for(&side) {
+            CardType::AGENT => {
+                card.flipped = true;
+                card.flipped_for = Some(side);
+                new_game.remaining_cards = new_game.duet_remaining_agents();
+                if new_game.remaining_cards.iter().all(|&agents| agents == 0) {
+                    new_game.game_status = GameStatus::WON {};
                 }
+                new_game
             },
             CardType::ASSASSIN => {
-                new_game.game_status = GameStatus::OVER { winner: Team::opposite(&new_game.turn_team) }
+                card.flipped = true;
+                card.flipped_for = Some(side);
+                new_game.game_status = GameStatus::LOST { reason: LossReason::Assassin };
+                new_game
+            },
+            _ => {
+                card.bystander_for.push(side);
+                new_game.next_turn()
             }
-            _ => new_game.turn_team = Team::opposite(&new_game.turn_team)
-        };
-
-        *card = Card {
-            flipped: true, 
-            ..card.clone()
-        };
-
-        new_game
+        }
     }
 
+    /// Ends the turn. Duet games use up a timer token, and once every agent on one
+    /// side is found, the other player gives every clue after that.
     pub fn next_turn(&self) -> Game {
         let mut new_game = self.clone();
-        new_game.turn_team = Team::opposite(&new_game.turn_team);
+        new_game.guesses_remaining = None;
+        let next_team = new_game.next_team(&new_game.turn_team);
+        let Some(timer_tokens) = new_game.timer_tokens.as_mut() else {
+            new_game.turn_team = next_team;
+            return new_game;
+        };
+
+        *timer_tokens = timer_tokens.saturating_sub(1);
+        if *timer_tokens == 0 && !new_game.game_status.is_over() {
+            new_game.game_status = GameStatus::LOST { reason: LossReason::OutOfTime };
+        }
+        if new_game.remaining_for(&next_team) > 0 {
+            new_game.turn_team = next_team;
+        }
         new_game
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Board, Card, CardType, Game};
+    use crate::client::ClientSession;
 
-    fn find_cards_in_board(board: &Board, card_type: &CardType) -> Vec<Card> {
-        let mut cards: Vec<Card> = Vec::new();
-        for row in 0..5 {
-            for col in 0..5 {
-                if *card_type == board[row][col].card_type {
-                    cards.push(board[row][col].clone());
-                }
-            }
-        }
-        cards
+    use crate::words::{WordList, WordLists};
+
+    use super::{Board, Card, CardType, Game, GameConfig, GameMode, GameStatus, LossReason, Team};
+
+    /// A seed where blue goes first.
+    const SEED: u64 = 0;
+
+    fn new_game() -> Game {
+        Game::new(&GameConfig::default(), WordLists::builtin().default_list(), SEED).unwrap()
     }
 
-    #[test]
-    fn fills_card() {
-        let mut board: Board = Default::default();
-        for _ in 0..3 {
-            Game::fill_card(&mut board, &CardType::BLUE);
-        }
+    fn new_game_with_config(config: &GameConfig) -> anyhow::Result<Game> {
+        Game::new(config, WordLists::builtin().default_list(), SEED)
+    }
 
-        for _ in 0..2 {
-            Game::fill_card(&mut board, &CardType::RED);
-        }
+    fn find_cards_in_board(board: &Board, card_type: &CardType) -> Vec<Card> {
+        board.iter()
+            .flatten()
+            .filter(|card| card.card_type == *card_type)
+            .cloned()
+            .collect()
+    }
 
-        Game::fill_card(&mut board, &CardType::ASSASSIN);
+    #[test]
+    fn counts_card_types() {
+        let config = GameConfig {
+            rows: 2,
+            cols: 3,
+            starting_team_cards: 3,
+            other_team_cards: 2,
+            assassins: 1,
+            bystanders: 0,
+            ..GameConfig::default()
+        };
+        let board: Board = vec![Game::card_types(&config, &Team::BLUE)
+            .into_iter()
+            .map(|card_type| Card { card_type, ..Card::default() })
+            .collect()];
 
         assert_eq!(find_cards_in_board(&board, &CardType::BLUE).len(), 3);
         assert_eq!(find_cards_in_board(&board, &CardType::RED).len(), 2);
         assert_eq!(find_cards_in_board(&board, &CardType::ASSASSIN).len(), 1);
+        assert_eq!(find_cards_in_board(&board, &CardType::BYSTANDER).len(), 0);
+    }
+
+    #[test]
+    fn fills_board_from_exactly_enough_words() {
+        let config = GameConfig::default();
+        let words = WordList::new("small", (0..25).map(|i| format!("WORD{}", i)).collect());
+        let game = Game::new(&config, &words, SEED).unwrap();
+        let mut board_words: Vec<&String> = game.board.iter().flatten().map(|card| &card.word).collect();
+        board_words.sort();
+        board_words.dedup();
+        assert_eq!(25, board_words.len());
+
+        let too_few_words = WordList::new("smaller", (0..24).map(|i| format!("WORD{}", i)).collect());
+        assert_eq!(
+            "The word list only has 24 words, but the board needs 25.",
+            Game::new(&config, &too_few_words, SEED).unwrap_err().to_string()
+        );
     }
 
     #[test]
     fn creates_new_board() {
-        let mut game = Game::new();
+        let mut game = new_game();
         assert_eq!(find_cards_in_board(&game.board, &CardType::BLUE).len(), 9);
         assert_eq!(find_cards_in_board(&game.board, &CardType::RED).len(), 8);
         assert_eq!(
@@ -275,7 +815,7 @@ mod tests {
             1
         );
 
-        game = Game::new_from_game(&game);
+        game = Game::new_from_game(&game, &game.config, WordLists::builtin().default_list(), None).unwrap();
         assert_eq!(find_cards_in_board(&game.board, &CardType::BLUE).len(), 8);
         assert_eq!(find_cards_in_board(&game.board, &CardType::RED).len(), 9);
         assert_eq!(
@@ -283,4 +823,371 @@ mod tests {
             1
         );
     }
+
+    #[test]
+    fn uses_up_guesses_from_clue() {
+        let game = new_game().give_clue("foo", 1);
+        let turn_team = game.turn_team.clone();
+        assert_eq!(Some(2), game.guesses_remaining);
+        assert_eq!(1, game.clues.len());
+        assert_eq!(turn_team, game.clues[0].team);
+
+        let own_cards = find_cards_in_board(&game.board, &CardType::from_team(&turn_team));
+
+        let game = game.flip_card(own_cards[0].coord);
+        assert_eq!(turn_team, game.turn_team);
+        assert_eq!(Some(1), game.guesses_remaining);
+
+        // Last guess ends the turn
+        let game = game.flip_card(own_cards[1].coord);
+        assert_eq!(game.next_team(&turn_team), game.turn_team);
+        assert_eq!(None, game.guesses_remaining);
+
+        // Wrong guesses end the turn right away
+        let game = game.give_clue("bar", 3);
+        let bystander = find_cards_in_board(&game.board, &CardType::BYSTANDER)[0].coord;
+        let game = game.flip_card(bystander);
+        assert_eq!(turn_team, game.turn_team);
+        assert_eq!(None, game.guesses_remaining);
+    }
+
+    #[test]
+    fn finds_unflipped_words() {
+        let game = new_game();
+        let word = game.board[1][2].word.clone();
+        assert!(game.is_unflipped_word(&word));
+        assert!(game.is_unflipped_word(&word.to_uppercase()));
+        assert!(!game.flip_card((1, 2)).is_unflipped_word(&word));
+    }
+
+    fn find_duet_card(game: &Game, side: &Team, card_type: CardType) -> (usize, usize) {
+        game.board.iter()
+            .flatten()
+            .find(|card| card.in_play_for(side) && *card.key_for(side) == card_type)
+            .unwrap()
+            .coord
+    }
+
+    #[test]
+    fn deals_duet_key() {
+        let game = new_game_with_config(&GameConfig::duet()).unwrap();
+        assert_eq!(vec![9, 9], game.remaining_cards);
+        assert_eq!(Some(9), game.timer_tokens);
+
+        let count = |side: &Team, card_type: CardType| {
+            game.board.iter().flatten().filter(|card| *card.key_for(side) == card_type).count()
+        };
+        for side in [Team::RED, Team::BLUE] {
+            assert_eq!(9, count(&side, CardType::AGENT));
+            assert_eq!(3, count(&side, CardType::ASSASSIN));
+            assert_eq!(13, count(&side, CardType::BYSTANDER));
+        }
+        let shared_agents = game.board.iter()
+            .flatten()
+            .filter(|card| *card.key_for(&Team::RED) == CardType::AGENT && *card.key_for(&Team::BLUE) == CardType::AGENT)
+            .count();
+        assert_eq!(3, shared_agents);
+
+        // Duet always deals the same key, so the board size and counts are fixed
+        let config = GameConfig { rows: 4, ..GameConfig::duet() };
+        assert!(new_game_with_config(&config).is_err());
+        let config = GameConfig { assassins: 20, ..GameConfig::duet() };
+        assert!(new_game_with_config(&config).is_err());
+        let config = GameConfig { mode: GameMode::Duet { timer_tokens: 0 }, ..GameConfig::duet() };
+        assert!(new_game_with_config(&config).is_err());
+    }
+
+    #[test]
+    fn plays_duet_turns() {
+        let game = new_game_with_config(&GameConfig::duet()).unwrap().give_clue("foo", 2);
+        let clue_giver = game.turn_team.clone();
+        let guesser = game.next_team(&clue_giver);
+
+        // Agents on the clue giver's side are found, and guessing goes on past the clue's count
+        let mut game = game;
+        for _ in 0..3 {
+            game = game.flip_card(find_duet_card(&game, &clue_giver, CardType::AGENT));
+        }
+        assert_eq!(clue_giver, game.turn_team);
+        assert_eq!(Some(3), game.guesses_remaining);
+        assert!(game.remaining_for(&clue_giver) <= 6);
+
+        // A bystander ends the turn but stays in play for the other side
+        let bystander = find_duet_card(&game, &clue_giver, CardType::BYSTANDER);
+        let game = game.flip_card(bystander);
+        let card = &game.board[bystander.0][bystander.1];
+        assert!(!card.flipped);
+        assert_eq!(vec![clue_giver.clone()], card.bystander_for);
+        assert!(!card.in_play_for(&clue_giver));
+        assert!(card.in_play_for(&guesser));
+        assert_eq!(guesser, game.turn_team);
+        assert_eq!(Some(8), game.timer_tokens);
+        assert_eq!(GameStatus::PLAYING {}, game.game_status);
+
+        // An assassin on the clue giver's side loses the game for both players
+        let game = game.give_clue("bar", 1);
+        let assassin = find_duet_card(&game, &guesser, CardType::ASSASSIN);
+        let lost = game.flip_card(assassin);
+        assert_eq!(GameStatus::LOST { reason: LossReason::Assassin }, lost.game_status);
+
+        // Finding every agent wins
+        let mut won = game.clone();
+        for card in won.board.iter_mut().flatten() {
+            if *card.key_for(&Team::RED) == CardType::AGENT || *card.key_for(&Team::BLUE) == CardType::AGENT {
+                card.flipped = true;
+            }
+        }
+        let last_agent = won.board.iter_mut()
+            .flatten()
+            .find(|card| *card.key_for(&guesser) == CardType::AGENT)
+            .unwrap();
+        last_agent.flipped = false;
+        let coord = last_agent.coord;
+        won = won.flip_card(coord);
+        assert_eq!(vec![0, 0], won.remaining_cards);
+        assert_eq!(GameStatus::WON {}, won.game_status);
+    }
+
+    #[test]
+    fn runs_out_of_duet_time() {
+        let config = GameConfig { mode: GameMode::Duet { timer_tokens: 2 }, ..GameConfig::duet() };
+        let game = new_game_with_config(&config).unwrap();
+        let starting_team = game.turn_team.clone();
+
+        let game = game.next_turn();
+        assert_ne!(starting_team, game.turn_team);
+        assert_eq!(GameStatus::PLAYING {}, game.game_status);
+
+        let game = game.next_turn();
+        assert_eq!(Some(0), game.timer_tokens);
+        assert_eq!(GameStatus::LOST { reason: LossReason::OutOfTime }, game.game_status);
+
+        // A side with no agents left doesn't give clues anymore
+        let mut game = new_game_with_config(&GameConfig::duet()).unwrap();
+        let other_team = game.next_team(&game.turn_team);
+        game.remaining_cards[other_team.index()] = 0;
+        assert_eq!(starting_team, game.next_turn().turn_team);
+    }
+
+    #[test]
+    fn shows_each_duet_player_their_side() {
+        let game = new_game_with_config(&GameConfig::duet()).unwrap().give_clue("foo", 1);
+        let clue_giver = game.turn_team.clone();
+        let red = ClientSession { team: Some(Team::RED), ..ClientSession::new(1, "foo") };
+        let blue = ClientSession { team: Some(Team::BLUE), ..ClientSession::new(2, "foo") };
+        let spectator = ClientSession::new(3, "foo");
+
+        for (session, side) in [(&red, Team::RED), (&blue, Team::BLUE)] {
+            let view = game.view_for(session);
+            assert!(view.board.iter().flatten().all(|card| {
+                card.card_type.as_ref() == Some(game.board[card.coord.0][card.coord.1].key_for(&side))
+            }));
+            assert_eq!(Some(9), view.timer_tokens);
+            // The seed would deal the other side of the key too
+            assert_eq!(None, view.seed);
+        }
+        assert!(game.view_for(&spectator).board.iter().flatten().all(|card| card.card_type.is_none()));
+
+        // Everyone sees a guessed agent, but a bystander only tells them about the clue giver's side
+        let agent = find_duet_card(&game, &clue_giver, CardType::AGENT);
+        let game = game.flip_card(agent);
+        assert_eq!(Some(CardType::AGENT), game.view_for(&spectator).board[agent.0][agent.1].card_type);
+        assert_eq!(Some(CardType::AGENT), game.revealed_card(agent).unwrap().card_type);
+
+        let bystander = find_duet_card(&game, &clue_giver, CardType::BYSTANDER);
+        let game = game.flip_card(bystander);
+        let revealed = game.revealed_card(bystander).unwrap();
+        assert!(!revealed.flipped);
+        assert_eq!(Some(CardType::BYSTANDER), revealed.card_type);
+        assert_eq!(vec![clue_giver], game.view_for(&spectator).board[bystander.0][bystander.1].bystander_for);
+        assert!(game.view_for(&spectator).board[bystander.0][bystander.1].card_type.is_none());
+    }
+
+    #[test]
+    fn hides_key_from_operatives() {
+        let game = new_game().flip_card((0, 0));
+        let operative = ClientSession::new(1, "foo");
+        let spymaster = ClientSession { is_spymaster: true, ..operative.clone() };
+
+        let operative_view = game.view_for(&operative);
+        assert_eq!(Some(game.board[0][0].card_type.clone()), operative_view.board[0][0].card_type);
+        assert!(operative_view.board.iter().flatten().skip(1).all(|card| card.card_type.is_none()));
+
+        assert_eq!(None, operative_view.seed);
+
+        let spymaster_view = game.view_for(&spymaster);
+        assert!(spymaster_view.board.iter().flatten().all(|card| card.card_type.is_some()));
+        assert_eq!(Some(game.seed), spymaster_view.seed);
+        assert_eq!(game.board[4][4].word, spymaster_view.board[4][4].word);
+    }
+
+    #[test]
+    fn creates_configured_board() {
+        let config = GameConfig {
+            rows: 4,
+            cols: 6,
+            starting_team_cards: 8,
+            other_team_cards: 7,
+            assassins: 3,
+            bystanders: 6,
+            ..GameConfig::default()
+        };
+        let game = new_game_with_config(&config).unwrap();
+        assert_eq!(4, game.board.len());
+        assert!(game.board.iter().all(|row| row.len() == 6));
+        assert_eq!((3, 5), game.board[3][5].coord);
+        assert_eq!(vec![8, 7], game.remaining_cards);
+        assert_eq!(find_cards_in_board(&game.board, &CardType::BLUE).len(), 8);
+        assert_eq!(find_cards_in_board(&game.board, &CardType::RED).len(), 7);
+        assert_eq!(find_cards_in_board(&game.board, &CardType::ASSASSIN).len(), 3);
+        assert_eq!(find_cards_in_board(&game.board, &CardType::BYSTANDER).len(), 6);
+
+        let game = Game::new_from_game(&game, &game.config, WordLists::builtin().default_list(), None).unwrap();
+        assert_eq!(vec![7, 8], game.remaining_cards);
+    }
+
+    #[test]
+    fn rejects_impossible_configs() {
+        let too_many_cards = GameConfig { bystanders: 8, ..GameConfig::default() };
+        assert_eq!(
+            "A 5x5 board has 25 cards, but the card counts add up to 26.",
+            new_game_with_config(&too_many_cards).unwrap_err().to_string()
+        );
+
+        let no_rows = GameConfig { rows: 0, ..GameConfig::default() };
+        assert!(new_game_with_config(&no_rows).is_err());
+
+        let no_team_cards = GameConfig { other_team_cards: 0, bystanders: 15, ..GameConfig::default() };
+        assert!(new_game_with_config(&no_team_cards).is_err());
+
+        let too_big = GameConfig {
+            rows: 30,
+            cols: 30,
+            starting_team_cards: 200,
+            other_team_cards: 200,
+            assassins: 100,
+            bystanders: 400,
+            ..GameConfig::default()
+        };
+        assert_eq!(
+            "Boards can have at most 10 rows and 10 columns.",
+            new_game_with_config(&too_big).unwrap_err().to_string()
+        );
+
+        // Sizes that would overflow are rejected instead of wrapping around to a valid count
+        let huge = GameConfig {
+            rows: 1 << 32,
+            cols: 1 << 32,
+            starting_team_cards: 1,
+            other_team_cards: 1,
+            assassins: usize::MAX,
+            bystanders: 2,
+            ..GameConfig::default()
+        };
+        assert!(huge.validate().is_err());
+        let overflowing_counts = GameConfig { assassins: usize::MAX, ..GameConfig::default() };
+        assert!(overflowing_counts.validate().is_err());
+
+        let one_team = GameConfig { teams: 1, ..GameConfig::default() };
+        assert!(new_game_with_config(&one_team).is_err());
+        let no_teams = GameConfig { teams: 0, ..GameConfig::default() };
+        assert!(new_game_with_config(&no_teams).is_err());
+        let game = new_game_with_config(&GameConfig::default()).unwrap();
+        assert!(game.new_from_current_game(&no_teams, WordLists::builtin().default_list(), None).is_err());
+        let five_teams = GameConfig { teams: 5, ..GameConfig::three_teams() };
+        assert!(new_game_with_config(&five_teams).is_err());
+        // Every team besides the starting team gets `other_team_cards`
+        let three_teams_on_small_board = GameConfig { teams: 3, ..GameConfig::default() };
+        assert!(new_game_with_config(&three_teams_on_small_board).is_err());
+    }
+
+    #[test]
+    fn plays_three_teams() {
+        let config = GameConfig { assassins: 2, bystanders: 9, ..GameConfig::three_teams() };
+        let game = new_game_with_config(&config).unwrap();
+        let first = game.turn_team.clone();
+        let second = game.next_team(&first);
+        let third = game.next_team(&second);
+        assert_eq!(first, game.next_team(&third));
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+        assert_ne!(first, third);
+
+        assert_eq!(3, game.remaining_cards.len());
+        assert_eq!(9, game.remaining_for(&first));
+        assert_eq!(8, game.remaining_for(&second));
+        assert_eq!(8, game.remaining_for(&third));
+        for team in [&first, &second, &third] {
+            let cards = find_cards_in_board(&game.board, &CardType::from_team(team)).len();
+            assert_eq!(game.remaining_for(team) as usize, cards);
+        }
+
+        // Flipping another team's card gives the turn to the next team, not the card's owner
+        let third_card = find_cards_in_board(&game.board, &CardType::from_team(&third))[0].coord;
+        let game = game.give_clue("foo", 1).flip_card(third_card);
+        assert_eq!(second, game.turn_team);
+        assert_eq!(7, game.remaining_for(&third));
+
+        // A team that flips an assassin is out, and the turn skips them
+        let assassins: Vec<(usize, usize)> = find_cards_in_board(&game.board, &CardType::ASSASSIN)
+            .iter()
+            .map(|card| card.coord)
+            .collect();
+        let game = game.give_clue("bar", 1).flip_card(assassins[0]);
+        assert_eq!(vec![second.clone()], game.eliminated);
+        assert_eq!(GameStatus::PLAYING {}, game.game_status);
+        assert_eq!(third, game.turn_team);
+        assert_eq!(first, game.next_turn().turn_team);
+        assert_eq!(third, game.next_turn().next_turn().turn_team);
+
+        // Finding the cards of a team that is out doesn't make them win
+        let mut out_of_cards = game.clone();
+        let second_cards: Vec<(usize, usize)> = find_cards_in_board(&game.board, &CardType::from_team(&second))
+            .iter()
+            .map(|card| card.coord)
+            .collect();
+        for coord in second_cards {
+            out_of_cards = out_of_cards.give_clue("baz", 1).flip_card(coord);
+        }
+        assert_eq!(GameStatus::PLAYING {}, out_of_cards.game_status);
+
+        // The last team left wins
+        let game = game.give_clue("baz", 1).flip_card(assassins[1]);
+        assert_eq!(GameStatus::OVER { winner: first.clone() }, game.game_status);
+
+        // The next game starts with the next team
+        let next_game = Game::new_from_game(&game, &config, WordLists::builtin().default_list(), None).unwrap();
+        assert_eq!(second, next_game.starting_team);
+        assert!(next_game.eliminated.is_empty());
+    }
+
+    #[test]
+    fn seeds_generate_identical_games() {
+        let words = WordLists::builtin();
+        let game = Game::new(&GameConfig::default(), words.default_list(), 42).unwrap();
+        let same_game = Game::new(&GameConfig::default(), words.default_list(), 42).unwrap();
+        assert_eq!(42, game.seed);
+        assert_eq!(game.starting_team, same_game.starting_team);
+        assert_eq!(game.board, same_game.board);
+
+        assert_eq!(Team::RED, game.starting_team);
+        let first_row: Vec<(&str, CardType)> = game.board[0].iter()
+            .map(|card| (card.word.as_str(), card.card_type.clone()))
+            .collect();
+        assert_eq!(vec![
+            ("OPERA", CardType::BYSTANDER),
+            ("POUND", CardType::RED),
+            ("PILOT", CardType::BLUE),
+            ("BLOCK", CardType::BLUE),
+            ("BAR", CardType::RED),
+        ], first_row);
+
+        let other_game = Game::new(&GameConfig::default(), words.default_list(), 43).unwrap();
+        assert_ne!(game.board, other_game.board);
+
+        // Seeding a follow up game ignores who started last game
+        let next_game = Game::new_from_game(&other_game, &game.config, words.default_list(), Some(42)).unwrap();
+        assert_eq!(game.starting_team, next_game.starting_team);
+        assert_eq!(game.board, next_game.board);
+    }
 }
@@ -0,0 +1,159 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::game::Game;
+
+/// Something a player did that changed the game.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "data")]
+#[serde(rename_all = "camelCase")]
+pub enum MoveAction {
+    /// Holds the whole starting game, so replays don't depend on word lists that
+    /// may have changed since.
+    NewGame { game: Game },
+    GiveClue { word: String, count: u8 },
+    FlipCard { coord: (usize, usize) },
+    NextTurn {},
+    Undo {},
+}
+
+/// An entry in a game's move log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Move {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub session_id: usize,
+    pub action: MoveAction,
+}
+
+impl Move {
+    pub fn new(session_id: usize, action: MoveAction) -> Move {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+        Move { timestamp, session_id, action }
+    }
+}
+
+/// A move and the game right after it was made.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayStep {
+    #[serde(rename = "move")]
+    pub game_move: Move,
+    pub game: Game,
+}
+
+/// Re-derives the game after every move in a log. The log has to start with a
+/// `NewGame` move.
+pub fn replay(moves: &[Move]) -> Result<Vec<ReplayStep>> {
+    Ok(replay_with_undo_history(moves)?.0)
+}
+
+/// The game as it was before the last card flip, if that flip can still be
+/// undone. Flips can only be undone until something else changes the game, like
+/// a clue or a new turn.
+pub fn undo_target(moves: &[Move]) -> Result<Option<Game>> {
+    Ok(replay_with_undo_history(moves)?.1.pop())
+}
+
+/// Replays the log, also returning the games from before each flip since the last
+/// clue or turn change, most recent last.
+fn replay_with_undo_history(moves: &[Move]) -> Result<(Vec<ReplayStep>, Vec<Game>)> {
+    let mut steps: Vec<ReplayStep> = Vec::with_capacity(moves.len());
+    let mut undo_history: Vec<Game> = Vec::new();
+
+    for (i, game_move) in moves.iter().enumerate() {
+        let game = match (&game_move.action, steps.last()) {
+            (MoveAction::NewGame { game }, _) => {
+                undo_history.clear();
+                game.clone()
+            },
+            (_, None) => bail!("The move log has to start with a new game."),
+            (MoveAction::GiveClue { word, count }, Some(step)) => {
+                undo_history.clear();
+                step.game.give_clue(word, *count)
+            },
+            (MoveAction::FlipCard { coord }, Some(step)) => {
+                step.game.board
+                    .get(coord.0)
+                    .and_then(|row| row.get(coord.1))
+                    .context(format!("Move {} flips a card that is not on the board.", i))?;
+                undo_history.push(step.game.clone());
+                step.game.flip_card(*coord)
+            },
+            (MoveAction::NextTurn {}, Some(step)) => {
+                undo_history.clear();
+                step.game.next_turn()
+            },
+            (MoveAction::Undo {}, Some(_)) => undo_history
+                .pop()
+                .context(format!("Move {} undoes a card flip that can't be undone.", i))?,
+        };
+        steps.push(ReplayStep { game_move: game_move.clone(), game });
+    }
+    Ok((steps, undo_history))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{game::{Game, GameConfig}, words::WordLists};
+
+    use super::{replay, undo_target, Move, MoveAction};
+
+    #[test]
+    fn replays_moves() {
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        let moves = vec![
+            Move::new(1, MoveAction::NewGame { game: game.clone() }),
+            Move::new(1, MoveAction::GiveClue { word: String::from("foo"), count: 2 }),
+            Move::new(2, MoveAction::FlipCard { coord: (0, 0) }),
+            Move::new(2, MoveAction::FlipCard { coord: (0, 1) }),
+            Move::new(2, MoveAction::Undo {}),
+            Move::new(3, MoveAction::NextTurn {}),
+        ];
+
+        let steps = replay(&moves).unwrap();
+        assert_eq!(6, steps.len());
+        assert_eq!(game, steps[0].game);
+        let after_flips = game.give_clue("foo", 2).flip_card((0, 0));
+        assert_eq!(after_flips.flip_card((0, 1)), steps[3].game);
+        assert_eq!(after_flips, steps[4].game);
+        assert_eq!(after_flips.next_turn(), steps[5].game);
+        assert_eq!(moves[5], steps[5].game_move);
+    }
+
+    #[test]
+    fn finds_flips_to_undo() {
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        let mut moves = vec![Move::new(1, MoveAction::NewGame { game: game.clone() })];
+        assert_eq!(None, undo_target(&moves).unwrap());
+
+        moves.push(Move::new(2, MoveAction::FlipCard { coord: (0, 0) }));
+        moves.push(Move::new(2, MoveAction::FlipCard { coord: (0, 1) }));
+        assert_eq!(Some(game.flip_card((0, 0))), undo_target(&moves).unwrap());
+        moves.push(Move::new(2, MoveAction::Undo {}));
+        assert_eq!(Some(game.clone()), undo_target(&moves).unwrap());
+        moves.push(Move::new(2, MoveAction::Undo {}));
+        assert_eq!(None, undo_target(&moves).unwrap());
+
+        // Anything other than a flip clears the history
+        moves.push(Move::new(2, MoveAction::FlipCard { coord: (0, 0) }));
+        moves.push(Move::new(3, MoveAction::NextTurn {}));
+        assert_eq!(None, undo_target(&moves).unwrap());
+    }
+
+    #[test]
+    fn rejects_broken_logs() {
+        assert!(replay(&[Move::new(1, MoveAction::NextTurn {})]).is_err());
+
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        let new_game = Move::new(1, MoveAction::NewGame { game });
+        assert!(replay(&[new_game.clone(), Move::new(1, MoveAction::FlipCard { coord: (5, 5) })]).is_err());
+        assert!(replay(&[new_game, Move::new(1, MoveAction::Undo {})]).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use actix::Addr;
+
+pub mod api;
+pub mod bot;
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod database;
+pub mod embeddings;
+pub mod event;
+pub mod game;
+pub mod history;
+pub mod metrics;
+pub mod server;
+pub mod simulation;
+pub mod words;
+
+use client::Heartbeat;
+use database::Database;
+use server::WsServer;
+
+#[derive(Clone)]
+pub struct AppData<T: 'static + Database + std::marker::Unpin> {
+    pub server: Addr<WsServer<T>>,
+    pub database: T,
+    pub heartbeat: Heartbeat,
+    /// Bearer token for the admin API, which is turned off without one.
+    pub admin_token: Option<String>,
+}
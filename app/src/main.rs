@@ -1,46 +1,63 @@
-use actix::{Actor, Addr};
+use std::{process::ExitCode, sync::Arc};
+
+use actix::Supervisor;
+use clap::Parser;
 use actix_web::{
-    dev::Server, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+    dev::Server, error::ErrorInternalServerError, web, App, Error, HttpRequest, HttpResponse,
+    HttpServer, Responder, Result,
 };
 use actix_web_actors::ws;
-use database::{Database, MemoryDatabase};
+use log::{debug, info};
 use mime_guess::from_path;
 use rust_embed::RustEmbed;
-use serde::{Deserialize, Serialize};
-
-mod client;
-mod database;
-mod event;
-mod game;
-mod server;
-
-use client::WsClient;
-use server::WsServer;
+use serde::Deserialize;
 
-#[derive(Clone)]
-struct AppData<T: 'static + Database + std::marker::Unpin> {
-    server: Addr<WsServer<T>>,
-    database: T,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct UserInfo {
-    username: String
-}
+use rust_codenames::{
+    api,
+    cli::{self, Cli, Command},
+    metrics,
+    client::WsClient,
+    config::{Config, ConfigArgs, DatabaseSetting},
+    database::{Database, MemoryDatabase, SqliteDatabase},
+    embeddings::Embeddings,
+    server::{hash_password, WsServer},
+    words::WordLists,
+    AppData,
+};
 
 #[derive(RustEmbed)]
 #[folder = "dist/"]
 struct Assets;
 
+#[derive(Deserialize)]
+struct JoinQuery {
+    password: Option<String>,
+    /// Lets a player who is about to resume their session back into a locked room.
+    resume: Option<String>,
+}
+
 async fn ws_index<T: Database + 'static + std::marker::Unpin + Clone>(
     path: web::Path<String>,
+    query: web::Query<JoinQuery>,
     req: HttpRequest,
     stream: web::Payload,
     data: web::Data<AppData<T>>
 ) -> Result<HttpResponse, Error> {
     let room = path.into_inner();
+    // Passwords are checked here, off the server actor, since hashing them is slow.
+    // A wrong password is passed on as no password, the server then rejects the
+    // connection with an error the player can see.
+    let JoinQuery { password, resume } = query.into_inner();
+    let password = password.filter(|password| !password.is_empty());
+    let password_hash = match (data.database.get_room(&room), password) {
+        (_, None) => None,
+        (Ok(existing_room), Some(password)) => web::block(move || existing_room.verify_password(&password)).await?,
+        (Err(_), Some(password)) => Some(
+            web::block(move || hash_password(&password)).await?.map_err(ErrorInternalServerError)?
+        ),
+    };
     let resp = ws::start(
-        WsClient::new(data.get_ref().server.clone(), &room.to_string(), data.database.clone()),
+        WsClient::new(data.get_ref().server.clone(), &room.to_string(), data.database.clone(), password_hash, resume, data.heartbeat),
         &req,
         stream,
     );
@@ -48,7 +65,7 @@ async fn ws_index<T: Database + 'static + std::marker::Unpin + Clone>(
 }
 
 fn handle_embedded_file(path: &str) -> HttpResponse {
-    println!("PATH: {}", path);
+    debug!("Serving embedded file {}.", path);
     match Assets::get(path) {
       Some(content) => HttpResponse::Ok()
         .content_type(from_path(path).first_or_octet_stream().as_ref())
@@ -69,34 +86,99 @@ async fn dist(path: web::Path<String>) -> impl Responder {
 
 async fn create_server<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
     app_data: web::Data<AppData<T>>,
-) -> Result<Server, Error> {
+    config: &Config,
+) -> std::io::Result<Server> {
     let server = HttpServer::new(move || {
         App::new()
             .app_data(app_data.clone())
             .service(web::scope("/ws").route("/{room}", web::get().to(ws_index::<T>)))
+            .service(web::scope("/api").configure(api::configure::<T>))
+            .route("/metrics", web::get().to(metrics::serve_metrics::<T>))
             .service(dist)
             .service(index)
     })
-    .bind(("0.0.0.0", 8080))?
-    .workers(4)
+    .bind((config.bind_address.as_str(), config.port))
+    .map_err(|e| std::io::Error::new(e.kind(), format!("Could not listen on {}:{}: {}", config.bind_address, config.port, e)))?
+    .workers(config.workers)
     .run();
-    Result::Ok(server)
+    Ok(server)
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("debug"));
+async fn run<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(database: T, config: Config) -> std::io::Result<()> {
+    let mut word_lists = WordLists::builtin();
+    if let Some(dir) = &config.word_lists_dir {
+        word_lists.load_dir(dir).map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+    }
+    info!("Available word lists: {:?}", word_lists.names());
 
-    let memory_database = MemoryDatabase::new();
-    let chat_server = WsServer::new(memory_database.clone()).start();
+    let embeddings = match &config.embeddings {
+        Some(path) => {
+            let embeddings = Embeddings::load(path, config.embedding_words)
+                .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+            info!("Loaded {} word vectors for bots from '{}'.", embeddings.len(), path.display());
+            Some(Arc::new(embeddings))
+        },
+        None => None,
+    };
+
+    let server_database = database.clone();
+    let chat_server = Supervisor::start(move |_| {
+        let server = WsServer::new(server_database, word_lists);
+        match embeddings {
+            Some(embeddings) => server.with_embeddings(embeddings),
+            None => server,
+        }
+    });
 
     let app_data = web::Data::new(AppData {
         server: chat_server,
-        database: memory_database,
+        database,
+        heartbeat: config.heartbeat(),
+        admin_token: config.admin_token.clone(),
     });
 
-    create_server(app_data)
-        .await
-        .unwrap()
-        .await
+    info!("Listening on {}:{} with {} workers.", config.bind_address, config.port, config.workers);
+    create_server(app_data, &config).await?.await
+}
+
+#[actix_web::main]
+async fn serve(args: ConfigArgs) -> anyhow::Result<()> {
+    let config = Config::load(args)?;
+    env_logger::Builder::new().parse_filters(&config.log_level).init();
+
+    match &config.database {
+        DatabaseSetting::Sqlite(path) => {
+            let database = SqliteDatabase::open(path)?;
+            info!(
+                "Using sqlite database at '{}' with {} rooms and {} sessions.",
+                path.display(),
+                database.get_rooms().map(|rooms| rooms.len()).unwrap_or(0),
+                database.get_sessions().map(|sessions| sessions.len()).unwrap_or(0)
+            );
+            Ok(run(database, config).await?)
+        },
+        DatabaseSetting::Memory => {
+            info!("Using in-memory database.");
+            Ok(run(MemoryDatabase::new(), config).await?)
+        },
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        None => serve(cli.serve),
+        Some(Command::Serve(args)) => serve(args),
+        Some(command) => cli::run(command),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            for cause in e.chain().skip(1) {
+                eprintln!("  {}", cause);
+            }
+            ExitCode::FAILURE
+        }
+    }
 }
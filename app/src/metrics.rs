@@ -0,0 +1,141 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Result;
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::{database::Database, event::ScrapeMetrics, game::{GameStatus, LossReason, Team}, AppData};
+
+/// Prometheus metrics for one server. Each server has its own registry, so
+/// servers started in tests don't clash over metric names.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Open websocket connections.
+    pub connections: IntGauge,
+    pub rooms: IntGauge,
+    pub games_in_progress: IntGauge,
+    /// Finished games, labelled by the winning team and whether the game was won
+    /// by finding every card or by the other teams flipping assassins. Duet
+    /// games are won by the players or by nobody, and can also run out of time.
+    pub games_finished: IntCounterVec,
+    /// Requests from players, labelled by request type.
+    pub requests: IntCounterVec,
+    /// Time the server spent handling each request, labelled by request type.
+    pub request_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics> {
+        let registry = Registry::new_custom(Some(String::from("codenames")), None)?;
+        let metrics = Metrics {
+            connections: IntGauge::new("connections", "Open websocket connections.")?,
+            rooms: IntGauge::new("rooms", "Rooms that exist.")?,
+            games_in_progress: IntGauge::new("games_in_progress", "Games in rooms that are still being played.")?,
+            games_finished: IntCounterVec::new(
+                Opts::new("games_finished_total", "Games that ended, by winner and how they were won."),
+                &["winner", "reason"],
+            )?,
+            requests: IntCounterVec::new(
+                Opts::new("requests_total", "Requests from players, by request type."),
+                &["type"],
+            )?,
+            request_duration: HistogramVec::new(
+                HistogramOpts::new("request_duration_seconds", "Time spent handling requests, by request type.")
+                    .buckets(exponential_buckets(0.0001, 4.0, 8)?),
+                &["type"],
+            )?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.connections.clone()))?;
+        metrics.registry.register(Box::new(metrics.rooms.clone()))?;
+        metrics.registry.register(Box::new(metrics.games_in_progress.clone()))?;
+        metrics.registry.register(Box::new(metrics.games_finished.clone()))?;
+        metrics.registry.register(Box::new(metrics.requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.request_duration.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Counts a game that just ended.
+    pub fn game_finished(&self, status: &GameStatus, by_assassin: bool) {
+        let (winner, reason) = match status {
+            GameStatus::PLAYING {} => return,
+            GameStatus::OVER { winner } => (
+                match winner {
+                    Team::RED => "red",
+                    Team::BLUE => "blue",
+                    Team::GREEN => "green",
+                    Team::YELLOW => "yellow",
+                },
+                if by_assassin { "assassin" } else { "cards" },
+            ),
+            GameStatus::WON {} => ("players", "cards"),
+            GameStatus::LOST { reason: LossReason::Assassin } => ("nobody", "assassin"),
+            GameStatus::LOST { reason: LossReason::OutOfTime } => ("nobody", "time"),
+        };
+        self.games_finished.with_label_values(&[winner, reason]).inc();
+    }
+
+    /// Sets the room and game gauges from what is in the database.
+    pub fn count_rooms<T: Database>(&self, database: &T) -> Result<()> {
+        let rooms = database.get_rooms()?;
+        let mut games_in_progress = 0;
+        for room in &rooms {
+            if let GameStatus::PLAYING {} = database.get_game(room.game_id)?.game_status {
+                games_in_progress += 1;
+            }
+        }
+        self.rooms.set(rooms.len() as i64);
+        self.games_in_progress.set(games_in_progress);
+        Ok(())
+    }
+
+    /// Every metric in the Prometheus text format.
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Serves the metrics for Prometheus to scrape.
+pub async fn serve_metrics<T: 'static + Database + Sync + Send + std::marker::Unpin + Clone>(
+    data: web::Data<AppData<T>>,
+) -> HttpResponse {
+    match data.server.send(ScrapeMetrics).await {
+        Ok(Ok(text)) => HttpResponse::Ok().content_type(TextEncoder::new().format_type()).body(text),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{Database, MemoryDatabase},
+        game::{Game, GameConfig, GameStatus, LossReason, Team},
+        words::WordLists,
+    };
+
+    use super::Metrics;
+
+    #[test]
+    fn renders_metrics() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+
+        let metrics = Metrics::new().unwrap();
+        metrics.count_rooms(&database).unwrap();
+        metrics.game_finished(&GameStatus::OVER { winner: Team::RED }, true);
+        metrics.game_finished(&GameStatus::LOST { reason: LossReason::OutOfTime }, false);
+        metrics.requests.with_label_values(&["flipCard"]).inc();
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains("codenames_rooms 1"));
+        assert!(text.contains("codenames_games_in_progress 1"));
+        assert!(text.contains("codenames_games_finished_total{reason=\"assassin\",winner=\"red\"} 1"));
+        assert!(text.contains("codenames_games_finished_total{reason=\"time\",winner=\"nobody\"} 1"));
+        assert!(text.contains("codenames_requests_total{type=\"flipCard\"} 1"));
+    }
+}
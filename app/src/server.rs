@@ -1,15 +1,31 @@
 use std::{
-    collections::{HashMap}
+    collections::{HashMap, HashSet},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use actix::{Actor, Addr, Context, Handler};
-use log::{debug, info};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Supervised};
+use anyhow::anyhow;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use log::{debug, error, info, warn};
+use rand::Rng;
 
-use crate::{game::{Game, GameStatus}, client::ClientSession};
+use crate::{game::{CardType, Game, GameConfig, GameMode, Team, UndoPermission}, history::{undo_target, Move, MoveAction}, client::{ClientSession, TeamRosters}, words::{validate_custom_words, WordList, WordLists}};
 use crate::{
-    client::WsClient,
+    bot::{Bot, BotRole},
+    client::ClientAddr,
     database::Database,
-    event::{ClientRequest, ClientRequestType, Event, EventMessage, NewClientConnection}
+    embeddings::Embeddings,
+    event::{
+        ClientControl, ClientDisconnected, ClientRequest, ClientRequestType, CloseRoom, ErrorCode, Event, EventMessage,
+        NewClientConnection, ScrapeMetrics,
+    },
+    metrics::Metrics,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -17,6 +33,12 @@ pub struct Room {
     pub name: String,
     pub game_id: usize,
     pub sessions: Vec<usize>,
+    /// Set for private rooms, players have to know the password to join.
+    pub password_hash: Option<String>,
+    /// The session that moderates the room, the first player to join unless the
+    /// host was handed over.
+    pub host: Option<usize>,
+    pub locked: bool,
 }
 
 impl Room {
@@ -25,183 +47,1295 @@ impl Room {
             name,
             game_id,
             sessions: Vec::new(),
+            password_hash: None,
+            host: None,
+            locked: false,
         }
     }
+
+    /// The host if they are still in the room.
+    pub fn current_host(&self) -> Option<usize> {
+        self.host.filter(|host| self.sessions.contains(host))
+    }
+
+    fn state_event(&self) -> Event {
+        Event::RoomState { host: self.current_host(), locked: self.locked }
+    }
+
+    /// Checks `password` against the room's and returns the hash it matched. Hashing
+    /// is slow on purpose, so this shouldn't be called from the server actor.
+    pub fn verify_password(&self, password: &str) -> Option<String> {
+        let hash = self.password_hash.as_ref()?;
+        let parsed_hash = PasswordHash::new(hash).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .ok()
+            .map(|_| hash.clone())
+    }
 }
 
+/// Hashes the password for a new private room. Like `Room::verify_password`, this
+/// is too slow to call from the server actor.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::encode_b64(&rand::thread_rng().gen::<[u8; 16]>())
+        .map_err(|e| anyhow!("Could not encode password salt: {}", e))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("Could not hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// Why the server could not carry out a request. The sender is sent the matching
+/// `Event::Error`, nobody else in the room is told.
+#[derive(Debug)]
+pub enum ServerError {
+    /// The request broke a rule of the game.
+    Rejected { code: ErrorCode, message: String },
+    RoomNotFound(String),
+    SessionNotFound(usize),
+    Database(anyhow::Error),
+    /// Handling the request panicked and the server is restarting.
+    Panicked,
+}
+
+impl ServerError {
+    pub fn rejected<S: Into<String>>(code: ErrorCode, message: S) -> ServerError {
+        ServerError::Rejected { code, message: message.into() }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServerError::Rejected { code, .. } => code.clone(),
+            ServerError::RoomNotFound(_) => ErrorCode::RoomNotFound,
+            ServerError::SessionNotFound(_) => ErrorCode::SessionNotFound,
+            ServerError::Database(_) | ServerError::Panicked => ErrorCode::ServerError,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Rejected { message, .. } => write!(f, "{}", message),
+            ServerError::RoomNotFound(name) => write!(f, "Room {} does not exist.", name),
+            ServerError::SessionNotFound(id) => write!(f, "Session {} does not exist.", id),
+            ServerError::Database(e) => write!(f, "Database error: {:#}", e),
+            ServerError::Panicked => write!(f, "Handling the request panicked."),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl From<ErrorCode> for ServerError {
+    fn from(code: ErrorCode) -> Self {
+        ServerError::rejected(code.clone(), code.message())
+    }
+}
+
+impl From<anyhow::Error> for ServerError {
+    fn from(e: anyhow::Error) -> Self {
+        ServerError::Database(e)
+    }
+}
+
+/// Server side failures only send the generic message so internal details stay in the logs.
+impl From<&ServerError> for Event {
+    fn from(error: &ServerError) -> Self {
+        match error {
+            ServerError::Database(_) | ServerError::Panicked => error.code().into(),
+            _ => Event::Error { code: error.code(), message: error.to_string() },
+        }
+    }
+}
+
+/// Checks that the player is on the team whose turn it is in a game that is still going.
+fn validate_turn(session: &ClientSession, game: &Game) -> Result<(), ErrorCode> {
+    if game.game_status.is_over() {
+        return Err(ErrorCode::GameOver);
+    }
+    match &session.team {
+        None => Err(ErrorCode::NoTeam),
+        Some(team) if *team != game.turn_team => Err(ErrorCode::NotYourTurn),
+        Some(_) => Ok(()),
+    }
+}
+
+/// In Duet games either player can end the turn, the one giving clues or the one guessing.
+fn validate_next_turn(session: &ClientSession, game: &Game) -> Result<(), ErrorCode> {
+    match game.config.mode {
+        GameMode::Classic => validate_turn(session, game),
+        GameMode::Duet { .. } if game.game_status.is_over() => Err(ErrorCode::GameOver),
+        GameMode::Duet { .. } if session.team.is_none() => Err(ErrorCode::NoTeam),
+        GameMode::Duet { .. } => Ok(()),
+    }
+}
+
+/// In Duet games the player who didn't give the clue guesses, and cards that were
+/// bystanders for the clue giver's side can't be guessed for them again.
+fn validate_flip_card(session: &ClientSession, game: &Game, coord: (usize, usize)) -> Result<(), ErrorCode> {
+    match game.config.mode {
+        GameMode::Classic => {
+            validate_turn(session, game)?;
+            if session.is_spymaster {
+                return Err(ErrorCode::SpymasterCannotFlip);
+            }
+        },
+        GameMode::Duet { .. } => {
+            if game.game_status.is_over() {
+                return Err(ErrorCode::GameOver);
+            }
+            match &session.team {
+                None => return Err(ErrorCode::NoTeam),
+                Some(team) if *team == game.turn_team => return Err(ErrorCode::CannotGuessOwnClue),
+                Some(_) => {},
+            }
+        },
+    }
+    if game.guesses_remaining.is_none() {
+        return Err(ErrorCode::NoClue);
+    }
+    let card = game.board
+        .get(coord.0)
+        .and_then(|row| row.get(coord.1))
+        .ok_or(ErrorCode::InvalidCard)?;
+    if !card.in_play_for(&game.turn_team) {
+        return Err(ErrorCode::CardAlreadyFlipped);
+    }
+    Ok(())
+}
+
+/// Checks a team someone asked to join is playing the game. `None` asks for a
+/// balanced team, which always is.
+fn validate_team(team: Option<&Team>, game: &Game) -> Result<(), ErrorCode> {
+    match team {
+        Some(team) if !game.config.teams().contains(team) => Err(ErrorCode::TeamNotInGame),
+        _ => Ok(()),
+    }
+}
+
+/// Checks a new connection can join the room. `password_hash` is the hash the
+/// player's password was checked against before connecting, so a room whose
+/// password changed in the meantime still turns them away.
+fn validate_join(room: &Room, password_hash: Option<&str>, resuming: bool) -> Result<(), ErrorCode> {
+    match &room.password_hash {
+        Some(hash) if Some(hash.as_str()) != password_hash => Err(ErrorCode::WrongPassword),
+        _ if room.locked && !resuming => Err(ErrorCode::RoomLocked),
+        _ => Ok(()),
+    }
+}
+
+fn validate_host(session: &ClientSession, room: &Room) -> Result<(), ErrorCode> {
+    if room.current_host() != Some(session.id) {
+        return Err(ErrorCode::NotHost);
+    }
+    Ok(())
+}
+
+/// Checks the host can kick or hand over to `id`, which has to be another player in the room.
+fn validate_host_target(session: &ClientSession, room: &Room, id: usize) -> Result<(), ErrorCode> {
+    validate_host(session, room)?;
+    if id == session.id || !room.sessions.contains(&id) {
+        return Err(ErrorCode::PlayerNotFound);
+    }
+    Ok(())
+}
+
+fn validate_undo(session: &ClientSession, game: &Game, can_undo: bool) -> Result<(), ErrorCode> {
+    if game.game_status.is_over() {
+        return Err(ErrorCode::GameOver);
+    }
+    if !can_undo {
+        return Err(ErrorCode::NothingToUndo);
+    }
+    match game.config.undo {
+        UndoPermission::Spymasters if !session.is_spymaster => Err(ErrorCode::UndoNotAllowed),
+        _ => Ok(()),
+    }
+}
+
+fn validate_give_clue(session: &ClientSession, game: &Game, word: &str) -> Result<(), ErrorCode> {
+    validate_turn(session, game)?;
+    // Both Duet players give clues from their own side of the key
+    if !session.is_spymaster && game.config.mode == GameMode::Classic {
+        return Err(ErrorCode::NotSpymaster);
+    }
+    if game.guesses_remaining.is_some() {
+        return Err(ErrorCode::ClueAlreadyGiven);
+    }
+    if word.is_empty() || word.chars().any(char::is_whitespace) {
+        return Err(ErrorCode::InvalidClue);
+    }
+    if game.is_unflipped_word(word) {
+        return Err(ErrorCode::ClueWordOnBoard);
+    }
+    Ok(())
+}
+
+/// How long a disconnected player's session is kept so they can resume it.
+const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
 pub struct WsServer<T: 'static + Database + std::marker::Unpin> {
     database: T,
-    clients: HashMap<usize, Addr<WsClient<T>>>,
+    clients: HashMap<usize, ClientAddr>,
+    /// Sessions played by bots rather than people.
+    bots: HashSet<usize>,
+    word_lists: WordLists,
+    /// When each session that is waiting to be resumed lost its connection.
+    disconnected_at: HashMap<usize, Instant>,
+    /// Players in each room who voted to undo the last flip. Any change to the game
+    /// clears the votes.
+    undo_votes: HashMap<String, HashSet<usize>>,
+    metrics: Metrics,
+    /// Word vectors for bots to play with, there are no bots without them.
+    embeddings: Option<Arc<Embeddings>>,
 }
 
 impl<T: 'static + Database + std::marker::Unpin> WsServer<T> {
-    pub fn new(database: T) -> Self {
+    pub fn new(database: T, word_lists: WordLists) -> Self {
         WsServer {
             database,
-            clients: HashMap::new()
+            clients: HashMap::new(),
+            bots: HashSet::new(),
+            word_lists,
+            disconnected_at: HashMap::new(),
+            undo_votes: HashMap::new(),
+            metrics: Metrics::new().expect("The server's metrics are valid."),
+            embeddings: None,
         }
     }
 
-    fn send_event(&mut self, client_request: ClientRequest) {
+    /// Lets hosts add bots to their rooms.
+    pub fn with_embeddings(self, embeddings: Arc<Embeddings>) -> Self {
+        WsServer { embeddings: Some(embeddings), ..self }
+    }
+
+    fn team_rosters(&self, room_name: &str) -> TeamRosters {
+        let sessions = self.database.get_room(room_name).map(|room| room.sessions).unwrap_or_default();
+        TeamRosters::from_sessions(sessions.iter().filter_map(|id| self.database.get_session(id).ok()))
+    }
+
+    /// Finds a word list by name, where the `custom` list is the one uploaded to the room.
+    fn word_list(&self, room_name: &str, name: &str) -> Option<WordList> {
+        if name == WordLists::CUSTOM {
+            let words = self.database.get_room_words(room_name).ok()??;
+            return Some(WordList::new(WordLists::CUSTOM, words));
+        }
+        self.word_lists.get(name).cloned()
+    }
+
+    fn room(&self, room_name: &str) -> Result<Room, ServerError> {
+        self.database.get_room(room_name).map_err(|_| ServerError::RoomNotFound(room_name.to_string()))
+    }
+
+    fn session(&self, session_id: usize) -> Result<ClientSession, ServerError> {
+        self.database.get_session(&session_id).map_err(|_| ServerError::SessionNotFound(session_id))
+    }
+
+    /// Tells the sender why their request failed. Requests that were rejected are
+    /// expected, anything else is logged as an error.
+    fn send_error(&self, sender_id: usize, room_name: &str, error: &ServerError) {
+        match error {
+            ServerError::Rejected { .. } => debug!("Rejecting request from {}: {}", sender_id, error),
+            _ => error!("Request from {} in room {} failed: {}", sender_id, room_name, error),
+        }
+        if let Some(client) = self.clients.get(&sender_id) {
+            let sender = self.session(sender_id).unwrap_or_else(|_| ClientSession::new(sender_id, room_name));
+            client.send(EventMessage {
+                sender,
+                room: room_name.to_string(),
+                event: error.into(),
+            });
+        }
+    }
+
+    /// Hands the sender's connection over to the session the token belongs to and
+    /// drops the sender's own session, returning the resumed session's id. If the
+    /// dropped session was hosting, the resumed one takes over.
+    fn resume_session(&mut self, sender_id: usize, room_name: &str, token: &str) -> Result<usize, ServerError> {
+        let resumed = self.database
+            .get_session_by_resume_token(token)
+            .ok()
+            .filter(|session| session.room == room_name)
+            .ok_or(ErrorCode::InvalidResumeToken)?;
+        if resumed.id == sender_id {
+            return Ok(sender_id);
+        }
+
+        let client = self.clients.remove(&sender_id).ok_or(ServerError::SessionNotFound(sender_id))?;
+        if self.room(room_name)?.host == Some(sender_id) {
+            self.database.set_room_host(room_name, Some(resumed.id))?;
+        }
+        self.database.remove_session(sender_id)?;
+        self.disconnected_at.remove(&resumed.id);
+        debug!("{} resumed session {}.", sender_id, resumed.id);
+
+        client.control(ClientControl::Reassign { session_id: resumed.id });
+        if let Some(replaced) = self.clients.insert(resumed.id, client.clone()) {
+            if replaced != client {
+                replaced.control(ClientControl::Replaced);
+            }
+        }
+        Ok(resumed.id)
+    }
+
+    /// Hands the room to its first player if its host left, returning whether the
+    /// host changed. Takes the fields it needs so it can run while events are being
+    /// sent to the room's clients.
+    fn replace_missing_host(database: &mut T, bots: &HashSet<usize>, room: &Room) -> Result<bool, ServerError> {
+        if room.current_host().is_some() {
+            return Ok(false);
+        }
+        let new_host = room.sessions.iter().find(|id| !bots.contains(id)).copied();
+        debug!("Host {:?} left room {}, handing over to {:?}.", room.host, room.name, new_host);
+        database.set_room_host(&room.name, new_host)?;
+        Ok(true)
+    }
+
+    /// Removes a session without a connection once `RESUME_GRACE_PERIOD` passes,
+    /// unless it's resumed first.
+    fn expire_session_later(&mut self, session_id: usize, room: String, timed_out: bool, ctx: &mut Context<Self>) {
+        self.disconnected_at.insert(session_id, Instant::now());
+        ctx.run_later(RESUME_GRACE_PERIOD, move |act, ctx| {
+            let expired = act.disconnected_at
+                .get(&session_id)
+                .is_some_and(|disconnected_at| disconnected_at.elapsed() >= RESUME_GRACE_PERIOD);
+            if !expired {
+                return;
+            }
+            act.disconnected_at.remove(&session_id);
+            let request = if timed_out {
+                ClientRequestType::TimedOut { id: session_id }
+            } else {
+                ClientRequestType::Disconnect { id: session_id }
+            };
+            <Self as Handler<ClientRequest>>::handle(act, ClientRequest { sender_id: session_id, room_name: room, request }, ctx);
+        });
+    }
+
+    fn send_event(&mut self, client_request: ClientRequest, ctx: &mut Context<Self>) -> Result<(), ServerError> {
         let ClientRequest {
             ref sender_id,
             ref room_name,
             request,
         } = client_request;
 
-        let room = self.database.get_room(room_name).unwrap();
-        let game = self.database.get_game(room.game_id).unwrap();
-        let sessions = self.database.get_room(room_name).unwrap().sessions;
+        // Once resumed, the rest of the request is handled as the resumed session.
+        let connected_as = *sender_id;
+        let sender_id = &match request {
+            ClientRequestType::Resume { ref token } => self.resume_session(*sender_id, room_name, token)?,
+            _ => *sender_id,
+        };
+
+        match request {
+            ClientRequestType::Connect { id } | ClientRequestType::Disconnect { id } | ClientRequestType::TimedOut { id }
+                if id != *sender_id =>
+            {
+                return Err(ServerError::rejected(ErrorCode::InvalidRequest, "Players can only connect or disconnect themselves."));
+            }
+            ClientRequestType::Disconnect { id } | ClientRequestType::TimedOut { id } => {
+                // A client that timed out also disconnects when it stops, so the
+                // session may already be gone.
+                self.clients.remove(&id);
+                self.bots.remove(&id);
+                self.disconnected_at.remove(&id);
+                if self.database.get_session(&id).is_err() {
+                    debug!("{} already left room {}.", id, room_name);
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+
+        let room = self.room(room_name)?;
+        let game = self.database.get_game(room.game_id)?;
+        let sessions = room.sessions.clone();
 
-        let sender_session = self.database.get_session(sender_id).unwrap();
+        let sender_session = self.session(*sender_id)?;
 
+        // Sessions restored from a persistent database may not have a connected
+        // client yet, so only send to the sessions we have an address for.
         let send_message_to_single_client = |session_id: usize, event: Event| {
-            self.clients.get(&session_id).unwrap().do_send(EventMessage {
-                sender: sender_session.clone(),
-                room: room_name.clone(),
-                event: event.clone()
-            })
+            if let Some(client) = self.clients.get(&session_id) {
+                client.send(EventMessage {
+                    sender: sender_session.clone(),
+                    room: room_name.clone(),
+                    event: event.clone()
+                })
+            }
         };
 
         let send_message_to_clients = |event: Event| {
             for id in &sessions {
                 debug!("Sending event to id {} with value {:?}", id, &event);
-                self.clients.get(&id).unwrap().do_send(EventMessage {
-                    sender: sender_session.clone(),
-                    room: room_name.clone(),
-                    event: event.clone(),
-                });
+                if let Some(client) = self.clients.get(id) {
+                    client.send(EventMessage {
+                        sender: sender_session.clone(),
+                        room: room_name.clone(),
+                        event: event.clone(),
+                    });
+                }
             }
         };
 
-        let send_game_state_update_to_clients = |game: &Game| {
+        // Every player gets their own view of the game so only spymasters see the key.
+        let send_game_state_update_to_clients = |database: &T, game_id: usize, game: &Game| {
             debug!("Sending game state update event to room {}.", &room_name);
             for id in &sessions {
                 debug!("Sending game state update event to id {}.", id);
-                self.clients.get(id).unwrap().do_send(EventMessage {
-                    sender: sender_session.clone(),
-                    room: room_name.clone(),
-                    event: Event::GameStateUpdate { game: game.clone() },
-                });
+                if let (Some(client), Ok(session)) = (self.clients.get(id), database.get_session(id)) {
+                    client.send(EventMessage {
+                        sender: sender_session.clone(),
+                        room: room_name.clone(),
+                        event: Event::GameStateUpdate { game_id, game: game.view_for(&session) },
+                    });
+                }
             }
         };
 
+        let send_team_rosters_to_clients = |rosters: TeamRosters| {
+            let TeamRosters { red, blue, green, yellow, unassigned } = rosters;
+            send_message_to_clients(Event::TeamRosters { red, blue, green, yellow, unassigned });
+        };
+
         match request {
             ClientRequestType::Connect { id } => {
                 debug!("{} connected", id);
-                let session = self.database.get_session(&id).unwrap();
+                let mut session = sender_session.clone();
+                if session.team.is_none() {
+                    let team = self.team_rosters(room_name).balanced_team_for(id, game.config.teams());
+                    debug!("Assigning {} to team {:?}.", id, team);
+                    session = ClientSession { team: Some(team), ..session };
+                    self.database.update_session(id, &session)?;
+                }
+                // Rooms restored from a persistent database may have lost their host
+                if room.current_host().is_none() && !self.bots.contains(&id) {
+                    self.database.set_room_host(room_name, Some(id))?;
+                }
                 send_message_to_single_client(*sender_id, Event::UpdateClientSession { session });
-                send_game_state_update_to_clients(&game);
+                send_game_state_update_to_clients(&self.database, room.game_id, &game);
+                send_team_rosters_to_clients(self.team_rosters(room_name));
+                send_message_to_clients(self.room(room_name)?.state_event());
             },
             ClientRequestType::SetName { name } => {
-                let existing_session = self.database.get_session(sender_id).unwrap();
-                let new_session = ClientSession { username: name.clone(), ..existing_session };
-                self.database.update_session(*sender_id, &new_session).unwrap();
+                let new_session = ClientSession { username: name.clone(), ..sender_session.clone() };
+                self.database.update_session(*sender_id, &new_session)?;
                 send_message_to_single_client(*sender_id, Event::UpdateClientSession { session: new_session });
                 send_message_to_clients(Event::SetName { id: *sender_id, name });
+                send_team_rosters_to_clients(self.team_rosters(room_name));
             },
-            ClientRequestType::Disconnect { id } => {
+            ClientRequestType::Disconnect { id } | ClientRequestType::TimedOut { id } => {
                 debug!("{} disconnected.", id);
-                self.database.remove_session(id).unwrap();
-                if self.database.get_room(room_name).unwrap().sessions.len() == 0 {
+                self.database.remove_session(id)?;
+                if self.room(room_name)?.sessions.iter().all(|id| self.bots.contains(id)) {
                     info!("There are no players left in room {}. Removing.", room_name);
-                    self.database.remove_room(room_name).ok();
-                    return;
+                    return <Self as Handler<CloseRoom>>::handle(self, CloseRoom { room: room_name.clone() }, ctx);
                 }
                 send_message_to_clients(Event::Disconnect { id });
-                send_game_state_update_to_clients(&game);
-            },
-            ClientRequestType::TimedOut { id } => {
-                self.database.remove_session(id).unwrap();
-                if self.database.get_room(room_name).unwrap().sessions.len() == 0 {
-                    info!("There are no players left in room {}. Removing.", room_name);
-                    self.database.remove_room(room_name).unwrap();
-                    return;
+                send_game_state_update_to_clients(&self.database, room.game_id, &game);
+                send_team_rosters_to_clients(self.team_rosters(room_name));
+                let remaining_room = self.room(room_name)?;
+                if Self::replace_missing_host(&mut self.database, &self.bots, &remaining_room)? {
+                    send_message_to_clients(self.room(room_name)?.state_event());
                 }
-                send_message_to_clients(Event::Disconnect { id });
-                send_game_state_update_to_clients(&game);
             },
             ClientRequestType::Message { text } => {
-                let sender_client_session = self.database.get_session(sender_id).unwrap();
                 send_message_to_clients(Event::Message {
-                    sender: sender_client_session,
+                    sender: sender_session.clone(),
                     text,
                 });
             },
             ClientRequestType::FlipCard { coord } => {
-                if let GameStatus::OVER { winner: _ } = game.game_status {
-                    debug!("Cannot flip a card in a finished game. Ignoring request.");
-                    return;
-                }
-                let new_game = self.database.flip_card(room.game_id, coord).unwrap();
-                let flipped_card = &new_game.board[coord.0][coord.1];
-                let new_event = Event::FlipCard {
-                    flipped_card: flipped_card.clone(),
-                };
-                send_message_to_clients(new_event);
-                send_game_state_update_to_clients(&new_game);
+                validate_flip_card(&sender_session, &game, coord)?;
+                let new_game = self.database.flip_card(room.game_id, coord)?;
+                self.database.log_move(room.game_id, &Move::new(*sender_id, MoveAction::FlipCard { coord }))?;
+                self.undo_votes.remove(room_name);
+                let flipped_card = new_game.revealed_card(coord).ok_or(ErrorCode::InvalidCard)?;
+                if new_game.game_status.is_over() {
+                    self.metrics.game_finished(&new_game.game_status, flipped_card.card_type == Some(CardType::ASSASSIN));
+                }
+                send_message_to_clients(Event::FlipCard { flipped_card });
+                send_game_state_update_to_clients(&self.database, room.game_id, &new_game);
             },
-            ClientRequestType::NewGame {} => {
-                let new_game = game.new_from_current_game();
-                self.database.update_game(room.game_id, &new_game).unwrap();
+            ClientRequestType::NewGame { config, word_list, seed } => {
+                validate_host(&sender_session, &room)?;
+                let word_list_name = word_list.unwrap_or_else(|| game.word_list.clone());
+                let words = self.word_list(room_name, &word_list_name).ok_or_else(|| ServerError::rejected(
+                    ErrorCode::UnknownWordList,
+                    format!(
+                        "There is no word list named '{}'. Choose one of: {}.",
+                        word_list_name,
+                        self.word_lists.names().iter().map(|name| name.as_str()).collect::<Vec<&str>>().join(", ")
+                    ),
+                ))?;
+                let new_game = game.new_from_current_game(config.as_ref().unwrap_or(&game.config), &words, seed)
+                    .map_err(|e| ServerError::rejected(ErrorCode::InvalidGameConfig, e.to_string()))?;
+                let game_id = self.database.create_game(room_name, &new_game)?;
+                self.database.log_move(game_id, &Move::new(*sender_id, MoveAction::NewGame { game: new_game.clone() }))?;
+                self.undo_votes.remove(room_name);
                 for session in &sessions {
-                    let client_session = self.database.get_session(session).unwrap();
-                    let new_session = ClientSession {is_spymaster: false, ..client_session.clone()};
-                    self.database.update_session(*session, &new_session).unwrap();
+                    let client_session = self.session(*session)?;
+                    // Players on a team the new game doesn't have join one that it does
+                    let team = match client_session.team {
+                        Some(team) if !new_game.config.teams().contains(&team) => {
+                            Some(self.team_rosters(room_name).balanced_team_for(*session, new_game.config.teams()))
+                        },
+                        team => team,
+                    };
+                    let new_session = ClientSession {is_spymaster: false, team, ..client_session};
+                    self.database.update_session(*session, &new_session)?;
                     send_message_to_single_client(*session, Event::UpdateClientSession { session: new_session });
                 }
                 send_message_to_clients(Event::NewGame {});
-                send_game_state_update_to_clients(&new_game);
+                send_game_state_update_to_clients(&self.database, game_id, &new_game);
+                send_team_rosters_to_clients(self.team_rosters(room_name));
             },
             ClientRequestType::SetSpyMaster { spymaster } => {
                 let updated_session = ClientSession {
                     is_spymaster: spymaster,
                     ..sender_session.clone()
                 };
-                self.database.update_session(*sender_id, &updated_session).unwrap();
+                self.database.update_session(*sender_id, &updated_session)?;
                 send_message_to_single_client(*sender_id, Event::UpdateClientSession { session: updated_session.clone() });
-                send_message_to_clients(Event::SetSpyMaster {  })
+                send_message_to_single_client(*sender_id, Event::GameStateUpdate { game_id: room.game_id, game: game.view_for(&updated_session) });
+                send_message_to_clients(Event::SetSpyMaster {  });
+                send_team_rosters_to_clients(self.team_rosters(room_name));
             },
             ClientRequestType::NextTurn {} => {
-                let new_game = self.database.next_turn(room.game_id).unwrap();
+                validate_next_turn(&sender_session, &game)?;
+                let new_game = self.database.next_turn(room.game_id)?;
+                if new_game.game_status.is_over() {
+                    self.metrics.game_finished(&new_game.game_status, false);
+                }
+                self.database.log_move(room.game_id, &Move::new(*sender_id, MoveAction::NextTurn {}))?;
+                self.undo_votes.remove(room_name);
                 send_message_to_clients(Event::NextTurn {  });
-                send_game_state_update_to_clients(&new_game);
-            }
+                send_game_state_update_to_clients(&self.database, room.game_id, &new_game);
+            },
+            ClientRequestType::GiveClue { word, count } => {
+                let word = word.trim();
+                validate_give_clue(&sender_session, &game, word)?;
+                let new_game = self.database.give_clue(room.game_id, word, count)?;
+                let clue = MoveAction::GiveClue { word: word.to_string(), count };
+                self.database.log_move(room.game_id, &Move::new(*sender_id, clue))?;
+                self.undo_votes.remove(room_name);
+                if let Some(clue) = new_game.clues.last() {
+                    send_message_to_clients(Event::GiveClue { clue: clue.clone() });
+                }
+                send_game_state_update_to_clients(&self.database, room.game_id, &new_game);
+            },
+            ClientRequestType::UploadWords { words } => {
+                validate_host(&sender_session, &room)?;
+                let words = validate_custom_words(words, game.config.card_count())
+                    .map_err(|e| ServerError::rejected(ErrorCode::InvalidWordList, e.to_string()))?;
+                self.database.set_room_words(room_name, &words)?;
+                send_message_to_clients(Event::UploadWords { word_count: words.len() });
+            },
+            ClientRequestType::Undo {} => {
+                let undo_to = undo_target(&self.database.get_moves(room.game_id)?)?;
+                validate_undo(&sender_session, &game, undo_to.is_some())?;
+                if game.config.undo == UndoPermission::Vote {
+                    // Bots and players who lost their connection can't vote, so they don't count
+                    let voters: Vec<usize> = sessions.iter()
+                        .filter(|id| !self.bots.contains(id) && !self.disconnected_at.contains_key(id))
+                        .copied()
+                        .collect();
+                    let votes = self.undo_votes.entry(room_name.clone()).or_default();
+                    votes.insert(*sender_id);
+                    votes.retain(|id| voters.contains(id));
+                    let (votes, needed) = (votes.len(), voters.len() / 2 + 1);
+                    send_message_to_clients(Event::UndoVote { id: *sender_id, votes, needed });
+                    if votes < needed {
+                        return Ok(());
+                    }
+                }
+                self.undo_votes.remove(room_name);
+                let restored_game = undo_to.ok_or(ErrorCode::NothingToUndo)?;
+                self.database.update_game(room.game_id, &restored_game)?;
+                self.database.log_move(room.game_id, &Move::new(*sender_id, MoveAction::Undo {}))?;
+                // In Duet games the undone guess may only have marked a bystander
+                let undone_card = game.board.iter().flatten().find(|card| {
+                    restored_game.board
+                        .get(card.coord.0)
+                        .and_then(|row| row.get(card.coord.1))
+                        .is_some_and(|restored_card| restored_card != *card)
+                });
+                if let Some(card) = undone_card.and_then(|card| game.revealed_card(card.coord)) {
+                    send_message_to_clients(Event::Undo { card });
+                }
+                send_game_state_update_to_clients(&self.database, room.game_id, &restored_game);
+            },
+            ClientRequestType::Resume { .. } => {
+                send_message_to_single_client(*sender_id, Event::UpdateClientSession { session: sender_session.clone() });
+                send_message_to_single_client(*sender_id, Event::GameStateUpdate { game_id: room.game_id, game: game.view_for(&sender_session) });
+                send_team_rosters_to_clients(self.team_rosters(room_name));
+                // Everyone else already saw the session the connection started with
+                if connected_as != *sender_id {
+                    send_message_to_clients(Event::Disconnect { id: connected_as });
+                    send_message_to_clients(room.state_event());
+                } else {
+                    send_message_to_single_client(*sender_id, room.state_event());
+                }
+            },
+            ClientRequestType::JoinTeam { team } => {
+                validate_team(team.as_ref(), &game)?;
+                let team = team.unwrap_or_else(|| self.team_rosters(room_name).balanced_team_for(*sender_id, game.config.teams()));
+                let updated_session = ClientSession {
+                    team: Some(team.clone()),
+                    ..sender_session.clone()
+                };
+                self.database.update_session(*sender_id, &updated_session)?;
+                send_message_to_single_client(*sender_id, Event::UpdateClientSession { session: updated_session });
+                send_message_to_clients(Event::JoinTeam { id: *sender_id, team });
+                send_team_rosters_to_clients(self.team_rosters(room_name));
+            },
+            ClientRequestType::Kick { id } => {
+                validate_host_target(&sender_session, &room, id)?;
+                info!("{} kicked {} from room {}.", sender_id, id, room_name);
+                self.database.remove_session(id)?;
+                self.bots.remove(&id);
+                self.disconnected_at.remove(&id);
+                // Tell the kicked player before their connection is closed
+                send_message_to_clients(Event::Kick { id });
+                send_team_rosters_to_clients(self.team_rosters(room_name));
+                let remaining_room = self.room(room_name)?;
+                if Self::replace_missing_host(&mut self.database, &self.bots, &remaining_room)? {
+                    send_message_to_clients(self.room(room_name)?.state_event());
+                }
+                if let Some(client) = self.clients.remove(&id) {
+                    client.control(ClientControl::Kicked);
+                }
+            },
+            ClientRequestType::TransferHost { id } => {
+                validate_host_target(&sender_session, &room, id)?;
+                self.database.set_room_host(room_name, Some(id))?;
+                send_message_to_clients(self.room(room_name)?.state_event());
+            },
+            ClientRequestType::LockRoom { locked } => {
+                validate_host(&sender_session, &room)?;
+                self.database.set_room_locked(room_name, locked)?;
+                send_message_to_clients(self.room(room_name)?.state_event());
+            },
+            ClientRequestType::AddBot { team, role } => {
+                validate_host(&sender_session, &room)?;
+                if game.config.mode != GameMode::Classic {
+                    return Err(ServerError::rejected(ErrorCode::InvalidGameConfig, "Bots only play classic games."));
+                }
+                validate_team(team.as_ref(), &game)?;
+                let embeddings = self.embeddings.clone().ok_or(ErrorCode::BotsUnavailable)?;
+                let (bot_id, _) = self.database.create_session(room_name)?;
+                let team = team.unwrap_or_else(|| self.team_rosters(room_name).balanced_team_for(bot_id, game.config.teams()));
+                let bot_session = ClientSession {
+                    username: String::from(match role {
+                        BotRole::Spymaster => "Spymaster Bot",
+                        BotRole::Operative => "Operative Bot",
+                    }),
+                    is_spymaster: role == BotRole::Spymaster,
+                    team: Some(team),
+                    ..self.session(bot_id)?
+                };
+                self.database.update_session(bot_id, &bot_session)?;
+                info!("{} added {:?} bot {} to room {}.", sender_id, role, bot_id, room_name);
+                let bot = Bot::new(ctx.address(), room_name, embeddings, role).start();
+                self.clients.insert(bot_id, ClientAddr::new(bot));
+                self.bots.insert(bot_id);
+                // The bot joins like a player who already picked a team
+                return self.send_event(ClientRequest {
+                    sender_id: bot_id,
+                    room_name: room_name.clone(),
+                    request: ClientRequestType::Connect { id: bot_id },
+                }, ctx);
+            },
         }
+        Ok(())
     }
 }
 
 /// Make actor from `ChatServer`
 impl<T: 'static + Database + std::marker::Unpin> Actor for WsServer<T> {
     type Context = Context<Self>;
+
+    /// Sessions restored from a persistent database lost their connections in the
+    /// restart, so they get the same grace period as any other dropped connection.
+    /// This also runs again when the supervisor restarts the server, which drops
+    /// the timers for sessions that were already waiting.
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let rooms = match self.database.get_rooms() {
+            Ok(rooms) => rooms,
+            Err(e) => return error!("Couldn't load rooms to expire their old sessions: {}", e),
+        };
+        for room in rooms {
+            for session_id in room.sessions {
+                if !self.clients.contains_key(&session_id) {
+                    self.expire_session_later(session_id, room.name.clone(), false, ctx);
+                }
+            }
+        }
+    }
 }
 
-impl<T: 'static + Database + std::marker::Unpin> Handler<NewClientConnection<T>> for WsServer<T> {
-    type Result = usize;
+/// The server is started with a `Supervisor`, which restarts it with the same
+/// state and mailbox if it stops after a request panics.
+impl<T: 'static + Database + std::marker::Unpin> Supervised for WsServer<T> {
+    fn restarting(&mut self, _ctx: &mut Self::Context) {
+        warn!("Restarting the server.");
+        self.clients.retain(|_, client| client.connected());
+    }
+}
 
-    fn handle(&mut self, msg: NewClientConnection<T>, _ctx: &mut Self::Context) -> Self::Result {
-        if self.database.get_room(&msg.room).is_err() {
-            self.database.create_room(&msg.room).unwrap();
-        }
+impl<T: 'static + Database + std::marker::Unpin> Handler<NewClientConnection> for WsServer<T> {
+    type Result = Result<usize, ServerError>;
 
-        let session_id = self.database.create_session(&msg.room).unwrap();
+    fn handle(&mut self, msg: NewClientConnection, ctx: &mut Self::Context) -> Self::Result {
+        let new_game = match self.database.get_room(&msg.room) {
+            Ok(room) => {
+                let resuming = msg.resume_token
+                    .and_then(|token| self.database.get_session_by_resume_token(&token).ok())
+                    .is_some_and(|session| session.room == msg.room);
+                validate_join(&room, msg.password_hash.as_deref(), resuming)?;
+                None
+            },
+            Err(_) => {
+                let game = Game::new(&GameConfig::default(), self.word_lists.default_list(), Game::random_seed())?;
+                self.database.create_room(&msg.room, &game)?;
+                if let Some(password_hash) = &msg.password_hash {
+                    self.database.set_room_password(&msg.room, Some(password_hash))?;
+                }
+                Some(game)
+            }
+        };
 
+        let (session_id, resume_token) = self.database.create_session(&msg.room)?;
+
+        // The first player hosts the room, and the first game is logged as if they started it
+        if let Some(game) = new_game {
+            self.database.set_room_host(&msg.room, Some(session_id))?;
+            let game_id = self.room(&msg.room)?.game_id;
+            self.database.log_move(game_id, &Move::new(session_id, MoveAction::NewGame { game }))?;
+        }
+
+        msg.addr.send(EventMessage {
+            sender: self.session(session_id)?,
+            room: msg.room.clone(),
+            event: Event::ResumeToken { token: resume_token },
+        });
         self.clients.insert(session_id, msg.addr);
 
-        self.send_event(ClientRequest {
+        if let Err(error) = self.send_event(ClientRequest {
             sender_id: session_id,
             room_name: msg.room.clone(),
             request: ClientRequestType::Connect { id: session_id },
-        });
+        }, ctx) {
+            self.send_error(session_id, &msg.room, &error);
+        }
 
-        session_id
+        Ok(session_id)
+    }
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Handler<ScrapeMetrics> for WsServer<T> {
+    type Result = Result<String, ServerError>;
+
+    fn handle(&mut self, _msg: ScrapeMetrics, _ctx: &mut Self::Context) -> Self::Result {
+        let connections = self.clients.keys().filter(|id| !self.bots.contains(id)).count();
+        self.metrics.connections.set(connections as i64);
+        self.metrics.count_rooms(&self.database)?;
+        Ok(self.metrics.render()?)
+    }
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Handler<CloseRoom> for WsServer<T> {
+    type Result = Result<(), ServerError>;
+
+    fn handle(&mut self, msg: CloseRoom, _ctx: &mut Self::Context) -> Self::Result {
+        let room = self.room(&msg.room)?;
+        info!("Closing room {} with {} players.", room.name, room.sessions.len());
+        for session_id in &room.sessions {
+            if let Some(client) = self.clients.remove(session_id) {
+                client.control(ClientControl::RoomClosed);
+            }
+            self.bots.remove(session_id);
+            self.disconnected_at.remove(session_id);
+            self.database.remove_session(*session_id)?;
+        }
+        self.undo_votes.remove(&room.name);
+        self.database.remove_room(&room.name)?;
+        Ok(())
+    }
+}
+
+impl<T: 'static + Database + std::marker::Unpin> Handler<ClientDisconnected> for WsServer<T> {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientDisconnected, ctx: &mut Self::Context) -> Self::Result {
+        let ClientDisconnected { session_id, room, addr, timed_out } = msg;
+        // The connection was replaced by one that resumed the session, or it was
+        // already handled.
+        if self.clients.get(&session_id) != Some(&addr) {
+            return;
+        }
+        debug!("{} lost its connection, keeping the session for {:?}.", session_id, RESUME_GRACE_PERIOD);
+        self.clients.remove(&session_id);
+        self.expire_session_later(session_id, room, timed_out, ctx);
     }
 }
 
 impl<T: 'static + Database + std::marker::Unpin> Handler<ClientRequest> for WsServer<T> {
     type Result = ();
 
-    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Self::Context) -> Self::Result {
-        self.send_event(msg);
+    fn handle(&mut self, msg: ClientRequest, ctx: &mut Self::Context) -> Self::Result {
+        let sender_id = msg.sender_id;
+        let room_name = msg.room_name.clone();
+        let request_type = msg.request.name();
+        self.metrics.requests.with_label_values(&[request_type]).inc();
+        let _timer = self.metrics.request_duration.with_label_values(&[request_type]).start_timer();
+        match panic::catch_unwind(AssertUnwindSafe(|| self.send_event(msg, ctx))) {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => self.send_error(sender_id, &room_name, &error),
+            Err(_) => {
+                self.send_error(sender_id, &room_name, &ServerError::Panicked);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use actix::{Actor, Addr, Context, Handler, Message, MessageResult};
+    use anyhow::anyhow;
+
+    use crate::{
+        bot::BotRole,
+        client::{ClientAddr, ClientSession},
+        database::{Database, MemoryDatabase},
+        embeddings::Embeddings,
+        event::{ClientControl, ClientRequest, ClientRequestType, ErrorCode, Event, EventMessage, NewClientConnection, ScrapeMetrics},
+        game::{CardType, Game, GameConfig, GameStatus, Team, UndoPermission},
+        history::{Move, MoveAction},
+        words::WordLists,
+    };
+
+    use super::{
+        hash_password, validate_flip_card, validate_give_clue, validate_host, validate_host_target, validate_join,
+        validate_next_turn, validate_team, validate_turn, validate_undo,
+        Room, ServerError, WsServer,
+    };
+
+    fn new_game() -> Game {
+        Game::new(&GameConfig::default(), WordLists::builtin().default_list(), 0).unwrap()
+    }
+
+    /// A connection that keeps every event it's sent.
+    #[derive(Default)]
+    struct RecordingClient {
+        events: Vec<Event>,
+    }
+
+    impl Actor for RecordingClient {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<EventMessage> for RecordingClient {
+        type Result = ();
+
+        fn handle(&mut self, msg: EventMessage, _ctx: &mut Self::Context) {
+            self.events.push(msg.event);
+        }
+    }
+
+    impl Handler<ClientControl> for RecordingClient {
+        type Result = ();
+
+        fn handle(&mut self, _msg: ClientControl, _ctx: &mut Self::Context) {}
+    }
+
+    /// Hands over the events received so far.
+    #[derive(Message)]
+    #[rtype(result = "Vec<Event>")]
+    struct TakeEvents;
+
+    impl Handler<TakeEvents> for RecordingClient {
+        type Result = MessageResult<TakeEvents>;
+
+        fn handle(&mut self, _msg: TakeEvents, _ctx: &mut Self::Context) -> Self::Result {
+            MessageResult(std::mem::take(&mut self.events))
+        }
+    }
+
+    async fn connect<T: 'static + Database + std::marker::Unpin>(server: &Addr<WsServer<T>>, room: &str) -> (usize, Addr<RecordingClient>) {
+        let client = RecordingClient::default().start();
+        let id = server.send(NewClientConnection {
+            room: String::from(room),
+            addr: ClientAddr::new(client.clone()),
+            password_hash: None,
+            resume_token: None,
+        }).await.unwrap().unwrap();
+        (id, client)
+    }
+
+    fn operative(team: Option<Team>) -> ClientSession {
+        ClientSession { team, ..ClientSession::new(1, "foo") }
+    }
+
+    #[test]
+    fn validates_card_flips() {
+        let game = new_game();
+        let session = operative(Some(game.turn_team.clone()));
+        assert_eq!(Err(ErrorCode::NoClue), validate_flip_card(&session, &game, (0, 0)));
+
+        let game = game.give_clue("foo", 2);
+        let turn_team = Some(game.turn_team.clone());
+        let other_team = Some(game.next_team(&game.turn_team));
+
+        assert_eq!(Ok(()), validate_flip_card(&operative(turn_team.clone()), &game, (0, 0)));
+        assert_eq!(Err(ErrorCode::NoTeam), validate_flip_card(&operative(None), &game, (0, 0)));
+        assert_eq!(Err(ErrorCode::NotYourTurn), validate_flip_card(&operative(other_team.clone()), &game, (0, 0)));
+        assert_eq!(Err(ErrorCode::InvalidCard), validate_flip_card(&operative(turn_team.clone()), &game, (5, 0)));
+
+        let spymaster = ClientSession { is_spymaster: true, ..operative(turn_team.clone()) };
+        assert_eq!(Err(ErrorCode::SpymasterCannotFlip), validate_flip_card(&spymaster, &game, (0, 0)));
+        assert_eq!(Ok(()), validate_turn(&spymaster, &game));
+
+        let flipped_game = game.flip_card((0, 0)).give_clue("bar", 1);
+        let session = operative(Some(flipped_game.turn_team.clone()));
+        assert_eq!(Err(ErrorCode::CardAlreadyFlipped), validate_flip_card(&session, &flipped_game, (0, 0)));
+    }
+
+    #[test]
+    fn validates_clues() {
+        let game = new_game();
+        let spymaster = ClientSession { is_spymaster: true, ..operative(Some(game.turn_team.clone())) };
+        let board_word = game.board[0][0].word.clone();
+
+        assert_eq!(Ok(()), validate_give_clue(&spymaster, &game, "foo"));
+        assert_eq!(Err(ErrorCode::NotSpymaster), validate_give_clue(&operative(Some(game.turn_team.clone())), &game, "foo"));
+        let other_spymaster = ClientSession { team: Some(game.next_team(&game.turn_team)), ..spymaster.clone() };
+        assert_eq!(Err(ErrorCode::NotYourTurn), validate_give_clue(&other_spymaster, &game, "foo"));
+        assert_eq!(Err(ErrorCode::InvalidClue), validate_give_clue(&spymaster, &game, ""));
+        assert_eq!(Err(ErrorCode::InvalidClue), validate_give_clue(&spymaster, &game, "foo bar"));
+        assert_eq!(Err(ErrorCode::ClueWordOnBoard), validate_give_clue(&spymaster, &game, &board_word.to_lowercase()));
+        assert_eq!(Err(ErrorCode::ClueAlreadyGiven), validate_give_clue(&spymaster, &game.give_clue("foo", 1), "bar"));
+    }
+
+    #[test]
+    fn validates_duet_moves() {
+        let game = Game::new(&GameConfig::duet(), WordLists::builtin().default_list(), 0).unwrap();
+        let clue_giver = operative(Some(game.turn_team.clone()));
+        let guesser = operative(Some(game.next_team(&game.turn_team)));
+
+        // Both players give clues without being spymasters, but only on their turn
+        assert_eq!(Ok(()), validate_give_clue(&clue_giver, &game, "foo"));
+        assert_eq!(Err(ErrorCode::NotYourTurn), validate_give_clue(&guesser, &game, "foo"));
+
+        let game = game.give_clue("foo", 1);
+        assert_eq!(Err(ErrorCode::CannotGuessOwnClue), validate_flip_card(&clue_giver, &game, (0, 0)));
+        assert_eq!(Err(ErrorCode::NoTeam), validate_flip_card(&operative(None), &game, (0, 0)));
+        assert_eq!(Ok(()), validate_next_turn(&clue_giver, &game));
+        assert_eq!(Ok(()), validate_next_turn(&guesser, &game));
+
+        // A bystander for the clue giver can't be guessed again until the other player gives a clue
+        let bystander = game.board.iter()
+            .flatten()
+            .find(|card| *card.key_for(&game.turn_team) == CardType::BYSTANDER)
+            .unwrap()
+            .coord;
+        assert_eq!(Ok(()), validate_flip_card(&guesser, &game, bystander));
+        let game = game.flip_card(bystander).give_clue("bar", 1);
+        assert_eq!(Ok(()), validate_flip_card(&clue_giver, &game, bystander));
+        let game = game.next_turn().give_clue("baz", 1);
+        assert_eq!(Err(ErrorCode::CardAlreadyFlipped), validate_flip_card(&guesser, &game, bystander));
+    }
+
+    #[test]
+    fn validates_teams() {
+        let game = new_game();
+        assert_eq!(Ok(()), validate_team(None, &game));
+        assert_eq!(Ok(()), validate_team(Some(&Team::BLUE), &game));
+        assert_eq!(Err(ErrorCode::TeamNotInGame), validate_team(Some(&Team::GREEN), &game));
+
+        let game = Game::new(&GameConfig::three_teams(), WordLists::builtin().default_list(), 0).unwrap();
+        assert_eq!(Ok(()), validate_team(Some(&Team::GREEN), &game));
+        assert_eq!(Err(ErrorCode::TeamNotInGame), validate_team(Some(&Team::YELLOW), &game));
+    }
+
+    #[test]
+    fn validates_join() {
+        let mut room = Room::new(String::from("foo"), 1);
+        assert_eq!(Ok(()), validate_join(&room, None, false));
+
+        room.password_hash = Some(hash_password("secret").unwrap());
+        let password_hash = room.verify_password("secret");
+        assert!(password_hash.is_some());
+        assert_eq!(None, room.verify_password("wrong"));
+        assert_eq!(Ok(()), validate_join(&room, password_hash.as_deref(), false));
+        assert_eq!(Err(ErrorCode::WrongPassword), validate_join(&room, None, false));
+
+        // A hash checked against an older password no longer gets in
+        let old_password_hash = hash_password("secret").unwrap();
+        assert_eq!(Err(ErrorCode::WrongPassword), validate_join(&room, Some(&old_password_hash), false));
+
+        // Locked rooms only let players resume their sessions
+        room.locked = true;
+        assert_eq!(Err(ErrorCode::RoomLocked), validate_join(&room, password_hash.as_deref(), false));
+        assert_eq!(Ok(()), validate_join(&room, password_hash.as_deref(), true));
+        assert_eq!(Err(ErrorCode::WrongPassword), validate_join(&room, None, true));
+    }
+
+    #[test]
+    fn validates_host() {
+        let host = ClientSession::new(1, "foo");
+        let player = ClientSession::new(2, "foo");
+        let room = Room { sessions: vec![1, 2], host: Some(1), ..Room::new(String::from("foo"), 1) };
+
+        assert_eq!(Ok(()), validate_host(&host, &room));
+        assert_eq!(Err(ErrorCode::NotHost), validate_host(&player, &room));
+        assert_eq!(Ok(()), validate_host_target(&host, &room, 2));
+        assert_eq!(Err(ErrorCode::NotHost), validate_host_target(&player, &room, 1));
+        assert_eq!(Err(ErrorCode::PlayerNotFound), validate_host_target(&host, &room, 1));
+        assert_eq!(Err(ErrorCode::PlayerNotFound), validate_host_target(&host, &room, 3));
+
+        // A host who left no longer counts
+        let room = Room { sessions: vec![2], ..room };
+        assert_eq!(Err(ErrorCode::NotHost), validate_host(&host, &room));
+    }
+
+    #[test]
+    fn validates_undo() {
+        let game = new_game();
+        let spymaster = ClientSession { is_spymaster: true, ..operative(None) };
+        assert_eq!(Err(ErrorCode::NothingToUndo), validate_undo(&spymaster, &game, false));
+        assert_eq!(Ok(()), validate_undo(&operative(None), &game, true));
+
+        let game = Game { config: GameConfig { undo: UndoPermission::Spymasters, ..GameConfig::default() }, ..game };
+        assert_eq!(Err(ErrorCode::UndoNotAllowed), validate_undo(&operative(None), &game, true));
+        assert_eq!(Ok(()), validate_undo(&spymaster, &game, true));
+
+        let game = Game { game_status: GameStatus::OVER { winner: Team::RED }, ..game };
+        assert_eq!(Err(ErrorCode::GameOver), validate_undo(&spymaster, &game, true));
+    }
+
+    #[test]
+    fn rejects_moves_in_finished_game() {
+        let mut game = new_game();
+        let assassin = game.board.iter()
+            .flatten()
+            .find(|card| card.card_type == CardType::ASSASSIN)
+            .unwrap()
+            .coord;
+        game = game.flip_card(assassin);
+
+        let session = operative(Some(game.turn_team.clone()));
+        assert_eq!(Err(ErrorCode::GameOver), validate_turn(&session, &game));
+        assert_eq!(Err(ErrorCode::GameOver), validate_flip_card(&session, &game, (0, 0)));
+    }
+
+    #[test]
+    fn hides_server_failures_from_clients() {
+        let Event::Error { code, message } = Event::from(&ServerError::from(ErrorCode::NoClue)) else { panic!() };
+        assert_eq!(ErrorCode::NoClue, code);
+        assert_eq!(ErrorCode::NoClue.message(), message);
+
+        let Event::Error { code, message } = Event::from(&ServerError::from(anyhow!("disk full"))) else { panic!() };
+        assert_eq!(ErrorCode::ServerError, code);
+        assert!(!message.contains("disk full"));
+    }
+
+    #[actix_web::test]
+    async fn survives_requests_for_missing_rooms_and_sessions() {
+        let mut database = MemoryDatabase::new();
+        database.create_room("foo", &new_game()).unwrap();
+        let server = WsServer::new(database.clone(), WordLists::builtin()).start();
+
+        let requests = vec![
+            ClientRequest { sender_id: 1, room_name: String::from("bar"), request: ClientRequestType::NextTurn {} },
+            ClientRequest { sender_id: 1, room_name: String::from("foo"), request: ClientRequestType::FlipCard { coord: (0, 0) } },
+            ClientRequest { sender_id: 1, room_name: String::from("foo"), request: ClientRequestType::Disconnect { id: 1 } },
+            ClientRequest { sender_id: 1, room_name: String::from("foo"), request: ClientRequestType::TimedOut { id: 1 } },
+        ];
+        for request in requests {
+            server.send(request).await.unwrap();
+        }
+        assert!(server.connected());
+        assert!(database.get_room("foo").is_ok());
+    }
+
+    #[actix_web::test]
+    async fn reports_metrics() {
+        let mut database = MemoryDatabase::new();
+        database.create_room("foo", &new_game()).unwrap();
+        let server = WsServer::new(database.clone(), WordLists::builtin()).start();
+
+        let request = ClientRequest { sender_id: 1, room_name: String::from("foo"), request: ClientRequestType::NextTurn {} };
+        server.send(request).await.unwrap();
+
+        let text = server.send(ScrapeMetrics).await.unwrap().unwrap();
+        assert!(text.contains("codenames_connections 0"));
+        assert!(text.contains("codenames_rooms 1"));
+        assert!(text.contains("codenames_requests_total{type=\"nextTurn\"} 1"));
+        assert!(text.contains("codenames_request_duration_seconds_count{type=\"nextTurn\"} 1"));
+    }
+
+    #[actix_web::test]
+    async fn resumes_session_while_hosting() {
+        let mut database = MemoryDatabase::new();
+        database.create_room("foo", &new_game()).unwrap();
+        // A session restored from the database, whose player reconnects
+        let (restored, token) = database.create_session("foo").unwrap();
+        let server = WsServer::new(database.clone(), WordLists::builtin()).start();
+
+        // The new connection's session becomes host before it can resume
+        let (fresh, _) = connect(&server, "foo").await;
+        let (_, other) = connect(&server, "foo").await;
+        assert_eq!(Some(fresh), database.get_room("foo").unwrap().current_host());
+        other.send(TakeEvents).await.unwrap();
+
+        let request = ClientRequestType::Resume { token };
+        server.send(ClientRequest { sender_id: fresh, room_name: String::from("foo"), request }).await.unwrap();
+        let room = database.get_room("foo").unwrap();
+        assert!(!room.sessions.contains(&fresh));
+        assert_eq!(Some(restored), room.current_host());
+
+        let events = other.send(TakeEvents).await.unwrap();
+        assert!(events.iter().any(|event| matches!(event, Event::Disconnect { id } if *id == fresh)));
+        assert!(events.iter().any(|event| matches!(event, Event::RoomState { host, .. } if *host == Some(restored))));
+        let Some(Event::TeamRosters { red, blue, .. }) = events.iter().find(|event| matches!(event, Event::TeamRosters { .. })) else { panic!() };
+        assert!(red.iter().chain(blue).all(|session| session.id != fresh));
+    }
+
+    #[actix_web::test]
+    async fn leaves_bots_out_of_undo_votes() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig { undo: UndoPermission::Vote, ..GameConfig::default() }, WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        let bots: Vec<usize> = (0..2).map(|_| database.create_session("foo").unwrap().0).collect();
+        let mut server = WsServer::new(database.clone(), WordLists::builtin());
+        server.bots.extend(bots);
+        let server = server.start();
+        let (first, _) = connect(&server, "foo").await;
+        let (second, _) = connect(&server, "foo").await;
+        let game_id = database.get_room("foo").unwrap().game_id;
+        database.log_move(game_id, &Move::new(first, MoveAction::NewGame { game })).unwrap();
+        database.flip_card(game_id, (0, 0)).unwrap();
+        database.log_move(game_id, &Move::new(first, MoveAction::FlipCard { coord: (0, 0) })).unwrap();
+        let undo = |sender_id| ClientRequest { sender_id, room_name: String::from("foo"), request: ClientRequestType::Undo {} };
+
+        // Two players and two bots only need both players to agree
+        server.send(undo(first)).await.unwrap();
+        assert!(database.get_game(game_id).unwrap().board[0][0].flipped);
+        server.send(undo(second)).await.unwrap();
+        assert!(!database.get_game(game_id).unwrap().board[0][0].flipped);
+    }
+
+    #[actix_web::test]
+    async fn drops_kicked_players_right_away() {
+        let mut database = MemoryDatabase::new();
+        database.create_room("foo", &new_game()).unwrap();
+        let server = WsServer::new(database.clone(), WordLists::builtin()).start();
+        let (host, _) = connect(&server, "foo").await;
+        let (kicked, kicked_client) = connect(&server, "foo").await;
+        let request = |sender_id, request| ClientRequest { sender_id, room_name: String::from("foo"), request };
+
+        server.send(request(host, ClientRequestType::Kick { id: kicked })).await.unwrap();
+        let events = kicked_client.send(TakeEvents).await.unwrap();
+        assert!(events.iter().any(|event| matches!(event, Event::Kick { id } if *id == kicked)));
+        assert!(!database.get_room("foo").unwrap().sessions.contains(&kicked));
+
+        // The kicked connection hears nothing more from the room, even before it closes
+        server.send(request(host, ClientRequestType::Message { text: String::from("bye") })).await.unwrap();
+        assert!(kicked_client.send(TakeEvents).await.unwrap().is_empty());
+        assert!(server.send(ScrapeMetrics).await.unwrap().unwrap().contains("codenames_connections 1"));
+    }
+
+    #[actix_web::test]
+    async fn moves_players_off_dropped_teams() {
+        let mut database = MemoryDatabase::new();
+        let game = Game::new(&GameConfig::three_teams(), WordLists::builtin().default_list(), 0).unwrap();
+        database.create_room("foo", &game).unwrap();
+        let (host, _) = database.create_session("foo").unwrap();
+        let (player, _) = database.create_session("foo").unwrap();
+        database.set_room_host("foo", Some(host)).unwrap();
+        database.update_session(host, &ClientSession { team: Some(Team::GREEN), ..ClientSession::new(host, "foo") }).unwrap();
+        database.update_session(player, &ClientSession { team: Some(Team::RED), ..ClientSession::new(player, "foo") }).unwrap();
+        let server = WsServer::new(database.clone(), WordLists::builtin()).start();
+
+        let request = ClientRequestType::NewGame { config: Some(GameConfig::duet()), word_list: None, seed: None };
+        server.send(ClientRequest { sender_id: host, room_name: String::from("foo"), request }).await.unwrap();
+        assert_eq!(Some(Team::BLUE), database.get_session(&host).unwrap().team);
+        assert_eq!(Some(Team::RED), database.get_session(&player).unwrap().team);
+    }
+
+    #[actix_web::test]
+    async fn bots_play_a_turn() {
+        let mut database = MemoryDatabase::new();
+        let game = new_game();
+        database.create_room("foo", &game).unwrap();
+        let (host, _) = database.create_session("foo").unwrap();
+        database.set_room_host("foo", Some(host)).unwrap();
+        let add_bot = |sender_id, role| ClientRequest {
+            sender_id,
+            room_name: String::from("foo"),
+            request: ClientRequestType::AddBot { team: Some(game.turn_team.clone()), role },
+        };
+
+        // Every card gets its own direction, and the clue points between two of the turn team's cards
+        let words: Vec<String> = game.board.iter().flatten().map(|card| card.word.replace(' ', "")).collect();
+        let vector = |hot: &dyn Fn(&String) -> bool| {
+            words.iter().map(|word| if hot(word) { "1" } else { "0" }).collect::<Vec<&str>>().join(" ")
+        };
+        let targets: Vec<String> = game.board.iter()
+            .flatten()
+            .filter(|card| card.card_type == CardType::from_team(&game.turn_team))
+            .take(2)
+            .map(|card| card.word.replace(' ', ""))
+            .collect();
+        let mut text: String = words.iter().map(|word| format!("{} {}\n", word, vector(&|other| other == word))).collect();
+        text.push_str(&format!("zzyzx {}\n", vector(&|word| targets.contains(word))));
+        let embeddings = Arc::new(Embeddings::parse(text.as_bytes(), 100).unwrap());
+
+        let server = WsServer::new(database.clone(), WordLists::builtin()).start();
+        server.send(add_bot(host, BotRole::Spymaster)).await.unwrap();
+        assert_eq!(1, database.get_room("foo").unwrap().sessions.len());
+
+        let server = WsServer::new(database.clone(), WordLists::builtin()).with_embeddings(embeddings).start();
+        let (player, _) = database.create_session("foo").unwrap();
+        server.send(add_bot(player, BotRole::Spymaster)).await.unwrap();
+        assert_eq!(2, database.get_room("foo").unwrap().sessions.len());
+
+        server.send(add_bot(host, BotRole::Operative)).await.unwrap();
+        server.send(add_bot(host, BotRole::Spymaster)).await.unwrap();
+        let room = database.get_room("foo").unwrap();
+        let bots: Vec<ClientSession> = room.sessions[2..].iter().map(|id| database.get_session(id).unwrap()).collect();
+        assert_eq!(
+            vec![(false, Some(game.turn_team.clone())), (true, Some(game.turn_team.clone()))],
+            bots.into_iter().map(|bot| (bot.is_spymaster, bot.team)).collect::<Vec<_>>()
+        );
+
+        // The spymaster gives a clue for two cards, and the operative flips both and ends the turn
+        let mut played = game.clone();
+        for _ in 0..250 {
+            played = database.get_game(room.game_id).unwrap();
+            if played.turn_team != game.turn_team {
+                break;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(1, played.clues.len());
+        assert_eq!(("ZZYZX", 2), (played.clues[0].word.as_str(), played.clues[0].count));
+        let flipped: Vec<String> = played.board.iter()
+            .flatten()
+            .filter(|card| card.flipped)
+            .map(|card| card.word.replace(' ', ""))
+            .collect();
+        assert_eq!(targets, flipped);
+
+        // Bots don't keep a room open once the players have left
+        for id in [host, player] {
+            let request = ClientRequest { sender_id: id, room_name: String::from("foo"), request: ClientRequestType::Disconnect { id } };
+            server.send(request).await.unwrap();
+        }
+        assert!(database.get_room("foo").is_err());
     }
 }
@@ -0,0 +1,353 @@
+use std::fmt;
+
+use anyhow::{ensure, Result};
+use rand::{seq::IteratorRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    bot::{choose_clue, choose_guess, ClueSettings, GuessSettings},
+    client::ClientSession,
+    embeddings::Embeddings,
+    game::{CardType, Clue, Game, GameConfig, GameMode, GameStatus, GameView, Team},
+    words::WordList,
+};
+
+/// Gives a team's clues in a simulated game.
+pub trait Spymaster {
+    /// A clue for the turn team, given the game as its spymaster sees it.
+    /// `None` passes the turn.
+    fn give_clue(&mut self, game: &GameView) -> Option<(String, u8)>;
+}
+
+/// Flips a team's cards in a simulated game.
+pub trait Operative {
+    /// Called before each game with a seed derived from the game's, so strategies
+    /// that use randomness play the same way every time the game is simulated.
+    fn new_game(&mut self, _seed: u64) {}
+
+    /// The next card to flip for `clue` after `guesses_made` guesses this turn,
+    /// given the game as operatives see it. `None` ends the turn.
+    fn guess(&mut self, game: &GameView, clue: &Clue, guesses_made: u8) -> Option<(usize, usize)>;
+}
+
+/// The spymaster bot from word embeddings.
+pub struct EmbeddingSpymaster<'a> {
+    pub embeddings: &'a Embeddings,
+    pub settings: ClueSettings,
+}
+
+impl Spymaster for EmbeddingSpymaster<'_> {
+    fn give_clue(&mut self, game: &GameView) -> Option<(String, u8)> {
+        choose_clue(self.embeddings, &game.board, &game.turn_team, &self.settings).map(|clue| (clue.word, clue.count))
+    }
+}
+
+/// The operative bot from word embeddings.
+pub struct EmbeddingOperative<'a> {
+    pub embeddings: &'a Embeddings,
+    pub settings: GuessSettings,
+}
+
+impl Operative for EmbeddingOperative<'_> {
+    fn guess(&mut self, game: &GameView, clue: &Clue, guesses_made: u8) -> Option<(usize, usize)> {
+        choose_guess(self.embeddings, &game.board, clue, guesses_made, &self.settings).map(|guess| guess.coord)
+    }
+}
+
+/// Flips random cards, up to as many as the clue was for. A baseline to compare
+/// other operatives against.
+pub struct RandomOperative {
+    rng: ChaCha8Rng,
+}
+
+impl RandomOperative {
+    pub fn new() -> Self {
+        RandomOperative { rng: ChaCha8Rng::seed_from_u64(0) }
+    }
+}
+
+impl Default for RandomOperative {
+    fn default() -> Self {
+        RandomOperative::new()
+    }
+}
+
+impl Operative for RandomOperative {
+    fn new_game(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    fn guess(&mut self, game: &GameView, clue: &Clue, guesses_made: u8) -> Option<(usize, usize)> {
+        if guesses_made > 0 && guesses_made >= clue.count {
+            return None;
+        }
+        game.board.iter().flatten().filter(|card| !card.flipped).map(|card| card.coord).choose(&mut self.rng)
+    }
+}
+
+/// The strategies one team plays with.
+pub struct TeamStrategy<'a> {
+    pub spymaster: Box<dyn Spymaster + 'a>,
+    pub operative: Box<dyn Operative + 'a>,
+}
+
+/// How a simulated game went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameResult {
+    pub seed: u64,
+    pub starting_team: Team,
+    /// `None` if nobody had won after the most turns a game is allowed.
+    pub winner: Option<Team>,
+    /// Turns taken by both teams together.
+    pub turns: usize,
+    pub assassin_flipped: bool,
+}
+
+/// Games longer than this are given up on, like when a spymaster keeps passing.
+pub const MAX_TURNS: usize = 100;
+
+/// Plays one game from start to finish with each team's strategies. Clues that
+/// aren't allowed, like words on the board, pass the turn, and so do guesses of
+/// cards that are already flipped.
+pub fn simulate_game<'a>(
+    config: &GameConfig,
+    words: &WordList,
+    seed: u64,
+    red: &mut TeamStrategy<'a>,
+    blue: &mut TeamStrategy<'a>,
+) -> Result<GameResult> {
+    ensure!(config.mode == GameMode::Classic, "Only classic games can be simulated.");
+    ensure!(config.teams == 2, "Only games between red and blue can be simulated.");
+    let mut game = Game::new(config, words, seed)?;
+    let starting_team = game.starting_team.clone();
+    red.operative.new_game(seed);
+    blue.operative.new_game(seed.wrapping_add(1));
+
+    let mut turns = 0;
+    let mut assassin_flipped = false;
+    while matches!(game.game_status, GameStatus::PLAYING {}) && turns < MAX_TURNS {
+        turns += 1;
+        let team = game.turn_team.clone();
+        let strategy = match team {
+            Team::RED => &mut *red,
+            _ => &mut *blue,
+        };
+        let spymaster = ClientSession { is_spymaster: true, team: Some(team.clone()), ..ClientSession::new(0, "simulation") };
+        let operative = ClientSession { is_spymaster: false, ..spymaster.clone() };
+
+        let clue = strategy.spymaster.give_clue(&game.view_for(&spymaster))
+            .filter(|(word, _)| is_allowed_clue(&game, word));
+        let Some((word, count)) = clue else {
+            game = game.next_turn();
+            continue;
+        };
+        game = game.give_clue(&word, count);
+        let clue = Clue { team: team.clone(), word, count };
+
+        while game.turn_team == team && matches!(game.game_status, GameStatus::PLAYING {}) {
+            let Some(guesses_remaining) = game.guesses_remaining else { break };
+            let guesses_made = count.saturating_add(1).saturating_sub(guesses_remaining);
+            let card = strategy.operative.guess(&game.view_for(&operative), &clue, guesses_made)
+                .and_then(|(row, col)| game.board.get(row)?.get(col))
+                .filter(|card| !card.flipped);
+            match card {
+                Some(card) => {
+                    assassin_flipped |= card.card_type == CardType::ASSASSIN;
+                    game = game.flip_card(card.coord);
+                }
+                None => game = game.next_turn(),
+            }
+        }
+    }
+
+    Ok(GameResult {
+        seed,
+        starting_team,
+        winner: match game.game_status {
+            GameStatus::OVER { winner } => Some(winner),
+            _ => None,
+        },
+        turns,
+        assassin_flipped,
+    })
+}
+
+fn is_allowed_clue(game: &Game, word: &str) -> bool {
+    !word.is_empty() && !word.chars().any(char::is_whitespace) && !game.is_unflipped_word(word)
+}
+
+/// Totals over many simulated games.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimulationReport {
+    pub games: usize,
+    pub red_wins: usize,
+    pub blue_wins: usize,
+    /// Games that hit `MAX_TURNS` without a winner.
+    pub unfinished: usize,
+    pub starting_team_wins: usize,
+    pub assassin_games: usize,
+    pub turns: usize,
+}
+
+impl SimulationReport {
+    pub fn add(&mut self, result: &GameResult) {
+        self.games += 1;
+        self.turns += result.turns;
+        match &result.winner {
+            Some(Team::RED) => self.red_wins += 1,
+            Some(Team::BLUE) => self.blue_wins += 1,
+            // Simulated games only have red and blue teams
+            Some(_) => {},
+            None => self.unfinished += 1,
+        }
+        if result.winner.as_ref() == Some(&result.starting_team) {
+            self.starting_team_wins += 1;
+        }
+        if result.assassin_flipped {
+            self.assassin_games += 1;
+        }
+    }
+
+    fn rate(&self, count: usize) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        count as f64 / self.games as f64
+    }
+
+    pub fn win_rate(&self, team: &Team) -> f64 {
+        match team {
+            Team::RED => self.rate(self.red_wins),
+            Team::BLUE => self.rate(self.blue_wins),
+            _ => 0.0,
+        }
+    }
+
+    /// How often the team that went first won, out of every game.
+    pub fn starting_team_win_rate(&self) -> f64 {
+        self.rate(self.starting_team_wins)
+    }
+
+    /// How often a game ended with someone flipping the assassin.
+    pub fn assassin_rate(&self) -> f64 {
+        self.rate(self.assassin_games)
+    }
+
+    pub fn average_turns(&self) -> f64 {
+        self.rate(self.turns)
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Games:               {}", self.games)?;
+        writeln!(f, "Red wins:            {:.1}%", self.win_rate(&Team::RED) * 100.0)?;
+        writeln!(f, "Blue wins:           {:.1}%", self.win_rate(&Team::BLUE) * 100.0)?;
+        writeln!(f, "Unfinished:          {}", self.unfinished)?;
+        writeln!(f, "Starting team wins:  {:.1}%", self.starting_team_win_rate() * 100.0)?;
+        writeln!(f, "Assassin flipped:    {:.1}%", self.assassin_rate() * 100.0)?;
+        writeln!(f, "Average turns:       {:.1}", self.average_turns())
+    }
+}
+
+/// Simulates `games` games, dealt from the seeds `seed`, `seed + 1` and so on, so
+/// a run can be repeated exactly.
+pub fn simulate<'a>(
+    config: &GameConfig,
+    words: &WordList,
+    seed: u64,
+    games: usize,
+    red: &mut TeamStrategy<'a>,
+    blue: &mut TeamStrategy<'a>,
+) -> Result<SimulationReport> {
+    let mut report = SimulationReport::default();
+    for i in 0..games as u64 {
+        report.add(&simulate_game(config, words, seed.wrapping_add(i), red, blue)?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        embeddings::Embeddings,
+        game::{Clue, GameConfig, GameView},
+        words::WordLists,
+    };
+
+    use super::{
+        simulate, simulate_game, EmbeddingOperative, EmbeddingSpymaster, Operative, RandomOperative, Spymaster,
+        TeamStrategy, MAX_TURNS,
+    };
+
+    /// Always gives the same clue.
+    struct FixedSpymaster;
+
+    impl Spymaster for FixedSpymaster {
+        fn give_clue(&mut self, _game: &GameView) -> Option<(String, u8)> {
+            Some((String::from("ZZYZX"), 2))
+        }
+    }
+
+    /// Never guesses.
+    struct PassingOperative;
+
+    impl Operative for PassingOperative {
+        fn guess(&mut self, _game: &GameView, _clue: &Clue, _guesses_made: u8) -> Option<(usize, usize)> {
+            None
+        }
+    }
+
+    fn random_team<'a>() -> TeamStrategy<'a> {
+        TeamStrategy { spymaster: Box::new(FixedSpymaster), operative: Box::new(RandomOperative::new()) }
+    }
+
+    #[test]
+    fn simulates_games() {
+        let words = WordLists::builtin().default_list().clone();
+        let config = GameConfig::default();
+
+        let report = simulate(&config, &words, 7, 50, &mut random_team(), &mut random_team()).unwrap();
+        assert_eq!(50, report.games);
+        assert_eq!(50, report.red_wins + report.blue_wins + report.unfinished);
+        assert!(report.turns >= 50);
+        // Random guessing finds the assassin a lot
+        assert!(report.assassin_games > 0);
+        assert_eq!(report, simulate(&config, &words, 7, 50, &mut random_team(), &mut random_team()).unwrap());
+        assert_ne!(report, simulate(&config, &words, 8, 50, &mut random_team(), &mut random_team()).unwrap());
+
+        // Nobody wins if nobody guesses
+        let passing = || TeamStrategy { spymaster: Box::new(FixedSpymaster), operative: Box::new(PassingOperative) };
+        let result = simulate_game(&config, &words, 7, &mut passing(), &mut passing()).unwrap();
+        assert_eq!((None, MAX_TURNS), (result.winner, result.turns));
+    }
+
+    #[test]
+    fn simulates_embedding_bots() {
+        let words = WordLists::builtin().default_list().clone();
+        let config = GameConfig::default();
+        // Words share a direction with the words next to them in the list, and
+        // the clue words each point at a run of list words
+        let dimensions = 32;
+        let vector = |i: usize| (0..dimensions).map(|d| if d == i % dimensions || d == (i + 1) % dimensions { "1" } else { "0" })
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let mut text: String = words.words.iter()
+            .enumerate()
+            .map(|(i, word)| format!("{} {}\n", word.replace(' ', ""), vector(i)))
+            .collect();
+        for i in 0..dimensions {
+            let clue: String = [b'Q', b'A' + (i / 26) as u8, b'A' + (i % 26) as u8].iter().map(|&c| c as char).collect();
+            text.push_str(&format!("{} {}\n", clue, vector(i)));
+        }
+        let embeddings = Embeddings::parse(text.as_bytes(), 10_000).unwrap();
+        let team = || TeamStrategy {
+            spymaster: Box::new(EmbeddingSpymaster { embeddings: &embeddings, settings: Default::default() }),
+            operative: Box::new(EmbeddingOperative { embeddings: &embeddings, settings: Default::default() }),
+        };
+
+        let report = simulate(&config, &words, 0, 5, &mut team(), &mut team()).unwrap();
+        assert_eq!(5, report.games);
+        assert_eq!(report, simulate(&config, &words, 0, 5, &mut team(), &mut team()).unwrap());
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::{HashMap, HashSet}, fs, path::Path, sync::Arc};
+
+use anyhow::{Result, Context, bail, ensure};
+use log::{info, warn};
+use rust_embed::RustEmbed;
+
+/// Word lists that are compiled into the binary, one word per line.
+#[derive(RustEmbed)]
+#[folder = "words/"]
+struct BuiltinWordLists;
+
+/// A named list of unique words cards can be drawn from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordList {
+    pub name: String,
+    pub words: Arc<Vec<String>>,
+}
+
+impl WordList {
+    /// Creates a list from `words`, normalized with `normalize_words` so the list
+    /// never holds the same word twice.
+    pub fn new(name: &str, words: Vec<String>) -> WordList {
+        WordList {
+            name: name.to_string(),
+            words: Arc::new(normalize_words(words)),
+        }
+    }
+
+    /// Parses a list with one word per line. Words are upper cased like the
+    /// built-in lists, and blank lines and repeated words are skipped.
+    pub fn parse(name: &str, text: &str) -> WordList {
+        WordList::new(name, text.lines().map(String::from).collect())
+    }
+}
+
+/// Most words a room can upload as its own list.
+pub const MAX_CUSTOM_WORDS: usize = 2000;
+
+/// Longest word, in characters, a custom list can contain.
+pub const MAX_WORD_LENGTH: usize = 32;
+
+/// Cleans up a word list uploaded for a room and checks it can fill a board
+/// with `card_count` cards.
+pub fn validate_custom_words<I: IntoIterator<Item = S>, S: AsRef<str>>(words: I, card_count: usize) -> Result<Vec<String>> {
+    let words = normalize_words(words);
+    if let Some(word) = words.iter().find(|word| word.chars().count() > MAX_WORD_LENGTH) {
+        bail!("'{}' is longer than {} characters.", word, MAX_WORD_LENGTH)
+    }
+    ensure!(
+        words.len() <= MAX_CUSTOM_WORDS,
+        "Word lists can have at most {} words, but this one has {}.", MAX_CUSTOM_WORDS, words.len()
+    );
+    ensure!(
+        words.len() >= card_count,
+        "The board needs {} unique words, but this list only has {}.", card_count, words.len()
+    );
+    Ok(words)
+}
+
+/// Trims and upper cases words, dropping blanks and duplicates while keeping the
+/// order the words first appeared in.
+pub fn normalize_words<I: IntoIterator<Item = S>, S: AsRef<str>>(words: I) -> Vec<String> {
+    let mut unique_words: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+    for word in words {
+        let word = word.as_ref().trim().to_uppercase();
+        if !word.is_empty() && seen.insert(word.clone()) {
+            unique_words.push(word);
+        }
+    }
+    unique_words
+}
+
+/// All word lists a room can pick from, loaded once at startup.
+#[derive(Debug, Clone)]
+pub struct WordLists {
+    lists: HashMap<String, WordList>,
+}
+
+impl WordLists {
+    pub const DEFAULT: &'static str = "default";
+
+    /// Reserved name for the list a room uploaded itself.
+    pub const CUSTOM: &'static str = "custom";
+
+    /// The word lists embedded in the binary.
+    pub fn builtin() -> WordLists {
+        let lists = BuiltinWordLists::iter()
+            .filter_map(|file| {
+                let name = Path::new(file.as_ref()).file_stem()?.to_str()?.to_string();
+                let content = BuiltinWordLists::get(&file)?;
+                let list = WordList::parse(&name, &String::from_utf8_lossy(&content.data));
+                Some((name, list))
+            })
+            .collect();
+        WordLists { lists }
+    }
+
+    /// Adds every `.txt` file in `dir` as a word list named after the file. Lists
+    /// with the same name as a built-in list replace it.
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir)
+            .context(format!("Could not read word list directory '{}'.", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if name == WordLists::CUSTOM {
+                warn!("Skipping '{}', the '{}' word list name is reserved for uploaded lists.", path.display(), name);
+                continue;
+            }
+            let text = fs::read_to_string(&path)
+                .context(format!("Could not read word list '{}'.", path.display()))?;
+            let list = WordList::parse(name, &text);
+            info!("Loaded word list '{}' with {} words.", name, list.words.len());
+            self.lists.insert(name.to_string(), list);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WordList> {
+        self.lists.get(name)
+    }
+
+    pub fn default_list(&self) -> &WordList {
+        self.get(WordLists::DEFAULT).expect("The default word list is embedded in the binary.")
+    }
+
+    pub fn names(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.lists.keys().collect();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WordList, WordLists, validate_custom_words, MAX_CUSTOM_WORDS};
+
+    #[test]
+    fn loads_builtin_lists() {
+        let word_lists = WordLists::builtin();
+        assert!(word_lists.names().contains(&&String::from(WordLists::DEFAULT)));
+        assert_eq!(400, word_lists.default_list().words.len());
+        assert!(word_lists.get("missing").is_none());
+    }
+
+    #[test]
+    fn parses_word_list() {
+        let list = WordList::parse("custom", "  apple\nBanana\n\napple\nCHERRY \n");
+        assert_eq!("custom", list.name);
+        assert_eq!(vec!["APPLE", "BANANA", "CHERRY"], *list.words);
+    }
+
+    #[test]
+    fn validates_custom_words() {
+        let words = validate_custom_words(["one", "two", " One", "three"], 3).unwrap();
+        assert_eq!(vec!["ONE", "TWO", "THREE"], words);
+
+        assert_eq!(
+            "The board needs 4 unique words, but this list only has 3.",
+            validate_custom_words(["one", "two", "one", "three"], 4).unwrap_err().to_string()
+        );
+        assert!(validate_custom_words(["a".repeat(33)], 1).is_err());
+
+        let too_many: Vec<String> = (0..=MAX_CUSTOM_WORDS).map(|i| i.to_string()).collect();
+        assert!(validate_custom_words(too_many, 25).is_err());
+    }
+
+    #[test]
+    fn loads_lists_from_directory() {
+        let dir = std::env::temp_dir().join(format!("codenames-words-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("animals.txt"), "cat\ndog\n").unwrap();
+        std::fs::write(dir.join("notes.md"), "not a word list").unwrap();
+
+        let mut word_lists = WordLists::builtin();
+        word_lists.load_dir(&dir).unwrap();
+        assert_eq!(vec!["CAT", "DOG"], *word_lists.get("animals").unwrap().words);
+        assert!(word_lists.get("notes").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,8 @@
 import React from "react";
 import { useMediaQuery } from "react-responsive";
-import { Card, CardType } from "./Room";
+import { Card, CardType, Team } from "./Room";
 
+// Duet players always see their own side of the key, which the server only sends them
 export function resolveCardTypeColor(card: Card, gameOver: boolean, isSpymaster: boolean): string {
   const {cardType, flipped} = card;
 
@@ -9,6 +10,10 @@ export function resolveCardTypeColor(card: Card, gameOver: boolean, isSpymaster:
     return "white";
   }
 
+  if (cardType === CardType.AGENT) {
+    return "green";
+  }
+
   if (cardType === CardType.BLUE) {
     return "blue";
   }
@@ -17,6 +22,14 @@ export function resolveCardTypeColor(card: Card, gameOver: boolean, isSpymaster:
     return "red"
   }
 
+  if (cardType === CardType.GREEN) {
+    return "green"
+  }
+
+  if (cardType === CardType.YELLOW) {
+    return "goldenrod"
+  }
+
   if (cardType === CardType.ASSASSIN) {
     return "grey"
   }
@@ -24,8 +37,11 @@ export function resolveCardTypeColor(card: Card, gameOver: boolean, isSpymaster:
   return "tan"
 }
 
-export default function CardCell(props: { card: Card, onFlip: (coord: [number, number]) => void, gameOver: boolean, isSpymaster: boolean }) {
-  const { card, onFlip, gameOver, isSpymaster } = props;
+export default function CardCell(props: { card: Card, onFlip: (coord: [number, number]) => void, gameOver: boolean, isSpymaster: boolean, isDuet: boolean }) {
+  const { card, onFlip, gameOver, isSpymaster, isDuet } = props;
+  const showKey = isSpymaster || isDuet;
+  // In Duet games a guessed bystander is only out of play for one side
+  const canFlip = !card.flipped && !gameOver && (isDuet || !isSpymaster);
 
   const isLandscape = useMediaQuery({query: "(orientation: landscape)"});
   const isDesktop = useMediaQuery({query: "(min-width: 1025px)"});
@@ -44,8 +60,8 @@ export default function CardCell(props: { card: Card, onFlip: (coord: [number, n
 
   return (
     <div style={{
-          backgroundColor: resolveCardTypeColor(card, gameOver, isSpymaster),
-          color: ((gameOver || isSpymaster || card.flipped) && ["BLUE", "RED"].includes(card.cardType)) ? "white" : "",
+          backgroundColor: resolveCardTypeColor(card, gameOver, showKey),
+          color: ((gameOver || showKey || card.flipped) && (card.cardType === CardType.BLUE || card.cardType === CardType.RED || card.cardType === CardType.GREEN || card.cardType === CardType.YELLOW || card.cardType === CardType.AGENT)) ? "white" : "",
           display: "flex",
           alignItems: "center",
           justifyContent: "center",
@@ -53,18 +69,21 @@ export default function CardCell(props: { card: Card, onFlip: (coord: [number, n
           borderRadius: "5px",
           border: "1px solid black",
           fontSize: resolveFontSize(),
-          cursor: card.flipped || gameOver || isSpymaster ? "" : "pointer",
-          opacity: isSpymaster && card.flipped ? "30%" : ""
+          cursor: canFlip ? "pointer" : "",
+          opacity: showKey && card.flipped ? "30%" : ""
         }}
         role="button"
         tabIndex={0}
         onClick={() => {
-          if (card.flipped || gameOver || isSpymaster) {
+          if (!canFlip) {
             return;
           }
           onFlip(card.coord)
         }}>
       {card.word}
+      {card.bystanderFor?.map(side => (
+        <span key={side} style={{color: side === Team.BLUE ? "blue" : "red", marginLeft: "4px"}}>&#9679;</span>
+      ))}
     </div>
   )
 }
\ No newline at end of file
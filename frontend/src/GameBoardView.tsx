@@ -7,16 +7,17 @@ export default function GameBoardView(props: {
     onFlip: (coord: [number, number]) => void, 
     style?: React.CSSProperties, 
     gameOver: boolean,
-    isSpymaster: boolean 
+    isSpymaster: boolean,
+    isDuet: boolean
   }) {
-  const { board, style, onFlip, gameOver, isSpymaster } = props;
+  const { board, style, onFlip, gameOver, isSpymaster, isDuet } = props;
 
   return (
     <div style={style}>
       {board === null ? null : (
-        <div style={{display: "grid", gridTemplateColumns: "repeat(5, 1fr)", gap: "8px", height: "100%"}}>
+        <div style={{display: "grid", gridTemplateColumns: `repeat(${board[0]?.length ?? 5}, 1fr)`, gap: "8px", height: "100%"}}>
           {board.map(row => row.map(card => (
-            <CardCell key={card.coord.toString()} card={card} onFlip={onFlip} gameOver={gameOver} isSpymaster={isSpymaster} />
+            <CardCell key={card.coord.toString()} card={card} onFlip={onFlip} gameOver={gameOver} isSpymaster={isSpymaster} isDuet={isDuet} />
           )))}
         </div>
       )}
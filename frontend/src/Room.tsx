@@ -9,22 +9,46 @@ import GameBoardView from "./GameBoardView";
 export enum CardType {
   RED = "RED",
   BLUE = "BLUE",
+  GREEN = "GREEN",
+  YELLOW = "YELLOW",
   BYSTANDER = "BYSTANDER",
-  ASSASSIN = "ASSASSIN"
+  ASSASSIN = "ASSASSIN",
+  AGENT = "AGENT"
 }
 
-export type Card = {word: string, cardType: CardType, flipped: boolean, coord: [number, number]}
+// In Duet games `bystanderFor` lists the sides whose clue the card was guessed as a bystander for
+export type Card = {word: string, cardType: CardType | null, flipped: boolean, coord: [number, number], bystanderFor?: Team[]}
 
 export type Board = Card[][]
 
-enum Team {
+export enum Team {
   RED = "RED",
-  BLUE = "BLUE"
+  BLUE = "BLUE",
+  GREEN = "GREEN",
+  YELLOW = "YELLOW"
+}
+
+// The order of the counts in `remainingCards`, and the turn order
+const TEAM_ORDER = [Team.BLUE, Team.RED, Team.GREEN, Team.YELLOW];
+
+export function teamColor(team: Team): string {
+  switch (team) {
+    case Team.BLUE:
+      return "blue";
+    case Team.RED:
+      return "red";
+    case Team.GREEN:
+      return "green";
+    case Team.YELLOW:
+      return "goldenrod";
+  }
 }
 
 enum GameStatusType {
   PLAYING = "PLAYING",
-  OVER = "OVER"
+  OVER = "OVER",
+  WON = "WON",
+  LOST = "LOST"
 }
 
 interface PlayingGameStatus {
@@ -37,11 +61,34 @@ interface OverGameStatus {
   data: {winner: Team}
 }
 
-type GameStatus = PlayingGameStatus | OverGameStatus
+interface WonGameStatus {
+  type: GameStatusType.WON
+  data: {}
+}
 
-export type Game = {board: Board, turnTeam: Team, startingTeam: Team, remainingCards: [number, number], gameStatus: GameStatus}
+interface LostGameStatus {
+  type: GameStatusType.LOST
+  data: {reason: "assassin" | "outOfTime"}
+}
+
+type GameStatus = PlayingGameStatus | OverGameStatus | WonGameStatus | LostGameStatus
+
+type GameMode = {type: "classic"} | {type: "duet", timerTokens: number}
+
+// `remainingCards` has a count for each team in `TEAM_ORDER`. In Duet games it counts the
+// agents left on the blue and red sides of the key
+export type Game = {
+  mode: GameMode,
+  board: Board,
+  turnTeam: Team,
+  startingTeam: Team,
+  remainingCards: [number, number],
+  gameStatus: GameStatus,
+  eliminated?: Team[],
+  timerTokens?: number
+}
 
-export type ClientSession = {id: number, username: string, room: string, is_spymaster: boolean}
+export type ClientSession = {id: number, username: string, room: string, is_spymaster: boolean, team?: Team | null}
 
 enum EventType {
   Connect = "connect",
@@ -54,7 +101,13 @@ enum EventType {
   FlipCard = "flipCard",
   UpdateClientSession = "updateClientSession",
   SetSpyMaster = "setSpyMaster",
-  NextTurn = "nextTurn"
+  NextTurn = "nextTurn",
+  ResumeToken = "resumeToken",
+  Undo = "undo",
+  UndoVote = "undoVote",
+  RoomState = "roomState",
+  Kick = "kick",
+  Error = "error"
 }
 
 interface ConnectEvent {
@@ -79,7 +132,7 @@ interface ChatMessageEvent {
 
 interface GameStateUpdateEvent {
   type: EventType.GameStateUpdate
-  data: {game: Game}
+  data: {gameId: number, game: Game}
 }
 
 interface NewGameEvent {
@@ -112,9 +165,42 @@ interface NextTurnEvent {
   data: {}
 }
 
+interface UndoEvent {
+  type: EventType.Undo
+  data: {card: Card}
+}
+
+interface UndoVoteEvent {
+  type: EventType.UndoVote
+  data: {id: number, votes: number, needed: number}
+}
+
+interface ResumeTokenEvent {
+  type: EventType.ResumeToken
+  data: {token: string}
+}
+
+type RoomState = {host: number | null, locked: boolean}
+
+interface RoomStateEvent {
+  type: EventType.RoomState
+  data: RoomState
+}
+
+interface KickEvent {
+  type: EventType.Kick
+  data: {id: number}
+}
+
+interface ErrorEvent {
+  type: EventType.Error
+  data: {code: string, message: string}
+}
+
 type Event = ConnectEvent | DisconnectEvent | TimedOutEvent | ChatMessageEvent | 
   GameStateUpdateEvent | NewGameEvent | SetNameEvent | FlipCardEvent | 
-  UpdateClientSessionEvent | SetSpyMasterEvent | NextTurnEvent
+  UpdateClientSessionEvent | SetSpyMasterEvent | NextTurnEvent | UndoEvent | UndoVoteEvent |
+  ResumeTokenEvent | RoomStateEvent | KickEvent | ErrorEvent
 
 interface EventMessage {
   sender: ClientSession
@@ -129,8 +215,10 @@ export default function Room() {
   const [messages, setMessages] = useState<(string | ReactElement)[]>([]);
 
   const [game, setGame] = useState<Game | null>(null);
+  const [gameId, setGameId] = useState<number | null>(null);
   const [username, setUsername] = useState<string>("");
   const [myClientSession, setMyClientSession] = useState<ClientSession>();
+  const [roomState, setRoomState] = useState<RoomState>({host: null, locked: false});
 
   const webSocket = useRef<WebSocket | null>(null);
   const prevGameState = useRef<Game>();
@@ -140,8 +228,10 @@ export default function Room() {
 
   const usernameIsSet = cookies.username !== undefined;
 
-  const gameOver = game ? game.gameStatus.type == GameStatusType.OVER : false;
+  const gameOver = game ? game.gameStatus.type != GameStatusType.PLAYING : false;
+  const isDuet = game?.mode.type === "duet";
   const isSpymaster = myClientSession ? myClientSession.is_spymaster : false;
+  const isHost = myClientSession !== undefined && myClientSession.id === roomState.host;
   // const showCards = gameOver || (myClientSession ? myClientSession.is_spymaster : false)
 
   const isLandscape = useMediaQuery({query: "(orientation: landscape)"});
@@ -159,11 +249,29 @@ export default function Room() {
       return;
     }
     
-    const uri = ((window.location.protocol === "https:") ? "wss://" : "ws://") + window.location.host + "/ws/" + (room ?? "main");
+    // Private rooms are joined, or created, with ?password=... on the room link
+    const passwordKey = `password:${room ?? "main"}`;
+    const password = sessionStorage.getItem(passwordKey) ?? new URLSearchParams(window.location.search).get("password");
+    let retryPassword: string | null = null;
+
+    // The token only lives as long as the tab so two tabs don't fight over one session
+    const resumeTokenKey = `resumeToken:${room ?? "main"}`;
+    let pendingResumeToken = sessionStorage.getItem(resumeTokenKey);
+    let connectionResumeToken: string | null = null;
+
+    // Locked rooms let players back in if they are resuming their session
+    const query = new URLSearchParams();
+    if (password) {
+      query.set("password", password);
+    }
+    if (pendingResumeToken) {
+      query.set("resume", pendingResumeToken);
+    }
+    const uri = ((window.location.protocol === "https:") ? "wss://" : "ws://") + window.location.host + "/ws/" + (room ?? "main")
+      + (query.toString() ? `?${query}` : "");
     webSocket.current = new WebSocket(uri);
 
-    webSocket.current.onopen = () => {
-      console.log("WEBSOCKET OPEN");
+    function sendName() {
       if (usernameIsSet) {
         webSocket.current?.send(JSON.stringify(
           {
@@ -174,8 +282,26 @@ export default function Room() {
       }
     }
 
+    webSocket.current.onopen = () => {
+      console.log("WEBSOCKET OPEN");
+      if (pendingResumeToken) {
+        webSocket.current?.send(JSON.stringify(
+          {
+            type: "resume",
+            data: {token: pendingResumeToken}
+          }
+        ));
+      } else {
+        sendName();
+      }
+    }
+
     webSocket.current.onclose = () => {
       console.log("WEBSOCKET CLOSED");
+      if (retryPassword !== null) {
+        sessionStorage.setItem(passwordKey, retryPassword);
+        setupWebSocket();
+      }
     }
     
     webSocket.current.onmessage = (msg: MessageEvent<string>) => {
@@ -198,11 +324,12 @@ export default function Room() {
             const {turnTeam} = event.data.game;
             setMessages(prev => [...prev, (
               <>
-                It is now <span style={{color: turnTeam === "BLUE" ? "blue" : "red"}}>{turnTeam}'s</span> turn! 
+                It is now <span style={{color: teamColor(turnTeam)}}>{turnTeam}'s</span> turn! 
               </>
             )])
           }
           setGame(event.data.game)
+          setGameId(event.data.gameId)
           break;
         case EventType.NewGame:
           setMessages(prev => [...prev, `${sender.username} restarted the game.`]);
@@ -214,7 +341,7 @@ export default function Room() {
           const {flippedCard: card} = event.data
           setMessages(prev => [...prev, (
             <>
-              {sender.username} flipped card "{card.word}". The card was <span style={{fontWeight: "bold", color: resolveCardTypeColor(card, true, true)}}>{card.cardType}</span>!
+              {sender.username} {card.flipped ? "flipped" : "guessed"} card "{card.word}". The card was <span style={{fontWeight: "bold", color: resolveCardTypeColor(card, true, true)}}>{card.cardType}</span>!
             </>
           )])
           break;
@@ -228,6 +355,46 @@ export default function Room() {
         case EventType.NextTurn:
           setMessages(prev => [...prev, `${sender.username} advanced the turn.`])
           break;
+        case EventType.Undo:
+          setMessages(prev => [...prev, `${sender.username} took back the flip of "${event.data.card.word}".`])
+          break;
+        case EventType.UndoVote:
+          setMessages(prev => [...prev, `${sender.username} wants to undo the last flip (${event.data.votes}/${event.data.needed} votes).`])
+          break;
+        case EventType.RoomState:
+          setRoomState(event.data)
+          break;
+        case EventType.Kick:
+          setMessages(prev => [...prev, `${sender.username} removed a player from the room.`])
+          break;
+        case EventType.ResumeToken:
+          if (password) {
+            sessionStorage.setItem(passwordKey, password);
+          }
+          // The first token belongs to the fresh session made for this connection,
+          // keep the old one until the resume is answered
+          connectionResumeToken = event.data.token;
+          if (!pendingResumeToken) {
+            sessionStorage.setItem(resumeTokenKey, event.data.token);
+          }
+          break;
+        case EventType.Error:
+          if (event.data.code === "invalidResumeToken") {
+            pendingResumeToken = null;
+            if (connectionResumeToken) {
+              sessionStorage.setItem(resumeTokenKey, connectionResumeToken);
+            } else {
+              sessionStorage.removeItem(resumeTokenKey);
+            }
+            sendName();
+          } else if (event.data.code === "wrongPassword") {
+            // The server hangs up after this, try again once it has
+            sessionStorage.removeItem(passwordKey);
+            retryPassword = window.prompt(event.data.message);
+          } else {
+            setMessages(prev => [...prev, event.data.message]);
+          }
+          break;
         default:
           console.error("Unrecognized event: ", event);
       }
@@ -291,6 +458,29 @@ export default function Room() {
     ))
   }
 
+  function switchMode(mode: "classic" | "threeTeams" | "duet") {
+    const config = {
+      classic: {rows: 5, cols: 5, startingTeamCards: 9, otherTeamCards: 8, assassins: 1, bystanders: 7, teams: 2},
+      threeTeams: {rows: 6, cols: 6, startingTeamCards: 9, otherTeamCards: 8, assassins: 1, bystanders: 10, teams: 3},
+      duet: {rows: 5, cols: 5, startingTeamCards: 9, otherTeamCards: 8, assassins: 1, bystanders: 7, mode: {type: "duet", timerTokens: 9}}
+    }[mode];
+    webSocket.current?.send(JSON.stringify(
+      {
+        type: "newGame",
+        data: {config}
+      }
+    ))
+  }
+
+  function joinTeam(team: Team) {
+    webSocket.current?.send(JSON.stringify(
+      {
+        type: "joinTeam",
+        data: {team}
+      }
+    ))
+  }
+
   function onFlip(coord: [number, number]) {
     webSocket.current?.send(JSON.stringify(
       {
@@ -318,6 +508,33 @@ export default function Room() {
     ))
   }
 
+  function undo() {
+    webSocket.current?.send(JSON.stringify(
+      {
+        type: "undo",
+        data: {}
+      }
+    ))
+  }
+
+  function lockRoom() {
+    webSocket.current?.send(JSON.stringify(
+      {
+        type: "lockRoom",
+        data: {locked: !roomState.locked}
+      }
+    ))
+  }
+
+  function addBot(role: "spymaster" | "operative") {
+    webSocket.current?.send(JSON.stringify(
+      {
+        type: "addBot",
+        data: {team: myClientSession?.team ?? null, role}
+      }
+    ))
+  }
+
   function onSetUsername() {
     const expireDate = new Date()
     expireDate.setFullYear(expireDate.getFullYear() + 5);
@@ -377,24 +594,61 @@ export default function Room() {
           <h2>Welcome to game {room}</h2>
           <div style={{display: "flex", flexDirection: "column", justifyContent: "center", gap: "10px"}}>
             <div style={{display: "flex", flexDirection: "row", gap: "25px", alignItems: "center"}}>
-              <div style={{width: "50px", textAlign: "center", display: "grid", gridTemplateColumns: "auto auto", gap: "1px", backgroundColor: "black", border: "1px solid black", borderRadius: "5px"}}>
-                <div style={{color: "blue", padding: "5px", backgroundColor: "white", borderRadius: "4px 0 0 4px"}}>{game.remainingCards[0]}</div>
-                <div style={{color: "red", padding: "5px", backgroundColor: "white", borderRadius: "0 4px 4px 0"}}>{game.remainingCards[1]}</div>
+              <div style={{textAlign: "center", display: "grid", gridTemplateColumns: `repeat(${game.remainingCards.length}, 25px)`, gap: "1px", backgroundColor: "black", border: "1px solid black", borderRadius: "5px", overflow: "hidden"}}>
+                {game.remainingCards.map((count, i) => (
+                  <div key={TEAM_ORDER[i]}
+                       style={{color: teamColor(TEAM_ORDER[i]), padding: "5px", backgroundColor: "white", textDecoration: game.eliminated?.includes(TEAM_ORDER[i]) ? "line-through" : ""}}>
+                    {count}
+                  </div>
+                ))}
               </div>
+              {isDuet && game.gameStatus.type == GameStatusType.PLAYING && (
+                <div>{game.timerTokens} turns left</div>
+              )}
               {game.gameStatus.type == GameStatusType.OVER ? (
                 <div>
-                  Game over! <span style={{width: "150px", color: game.gameStatus.data.winner === Team.BLUE ? "blue" : "red"}}>{game.gameStatus.data.winner}</span> team wins!
+                  Game over! <span style={{width: "150px", color: teamColor(game.gameStatus.data.winner)}}>{game.gameStatus.data.winner}</span> team wins!
+                </div>
+              ) : game.gameStatus.type == GameStatusType.WON ? (
+                <div>Every agent was found, you win!</div>
+              ) : game.gameStatus.type == GameStatusType.LOST ? (
+                <div>
+                  Game over! {game.gameStatus.data.reason === "assassin" ? "An assassin was contacted." : "You ran out of time."}
+                </div>
+              ) : isDuet ? (
+                <div style={{width: "150px", color: teamColor(game.turnTeam)}}>
+                  {game.turnTeam} gives the clue!
                 </div>
               ) : (
-                <div style={{width: "150px", color: game.turnTeam === Team.BLUE ? "blue" : "red"}}>
+                <div style={{width: "150px", color: teamColor(game.turnTeam)}}>
                   {game.turnTeam}'s turn!
                 </div>
               )}
             </div>
             <div style={{display: "flex", gap: "10px"}}>
-              <button onClick={restartGame}>Restart</button>
-              <button onClick={setSpymaster}>Spymaster</button>
+              {isHost && <button onClick={restartGame}>Restart</button>}
+              {isHost && (
+                <select value="" onChange={evt => switchMode(evt.target.value as "classic" | "threeTeams" | "duet")}>
+                  <option value="" disabled>New game...</option>
+                  <option value="classic">Two teams</option>
+                  <option value="threeTeams">Three teams</option>
+                  <option value="duet">Duet</option>
+                </select>
+              )}
+              {isHost && <button onClick={lockRoom}>{roomState.locked ? "Unlock room" : "Lock room"}</button>}
+              {isHost && !isDuet && <button onClick={() => addBot("spymaster")}>Add spymaster bot</button>}
+              {isHost && !isDuet && <button onClick={() => addBot("operative")}>Add operative bot</button>}
+              {isDuet ? (
+                <>
+                  <button onClick={() => joinTeam(Team.RED)}>Red side</button>
+                  <button onClick={() => joinTeam(Team.BLUE)}>Blue side</button>
+                </>
+              ) : (
+                <button onClick={setSpymaster}>Spymaster</button>
+              )}
               <button onClick={nextTurn}>Next turn</button>
+              <button onClick={undo}>Undo</button>
+              {gameOver && gameId !== null && <a href={`/api/games/${gameId}`} download={`codenames-${gameId}.json`}>Export game</a>}
             </div>
           </div>
         </div>
@@ -410,7 +664,8 @@ export default function Room() {
           <GameBoardView board={game.board}
                          onFlip={onFlip}
                          gameOver={gameOver}
-                         isSpymaster={isSpymaster} />
+                         isSpymaster={isSpymaster}
+                         isDuet={isDuet} />
           <div style={{display: "flex",
                       flexDirection: "column", 
                       height: "100%",